@@ -0,0 +1,97 @@
+//! Load generation shared by the benchmarks.
+
+use std::env;
+use std::io;
+use std::net::{SocketAddr, TcpListener as StdTcpListener, TcpStream as StdTcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const CHUNK: usize = 64 * 1024;
+
+pub fn env_usize(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+pub fn free_addr() -> SocketAddr {
+    StdTcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to find a free port")
+}
+
+pub fn wait_for_listener(addr: SocketAddr) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while StdTcpStream::connect(addr).is_err() {
+        assert!(Instant::now() < deadline, "proxy didn't start listening");
+        thread::sleep(Duration::from_millis(20));
+    }
+    // Let the proxy finish with the probe connection before measuring.
+    thread::sleep(Duration::from_millis(200));
+}
+
+pub async fn echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut read, mut write) = stream.split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+                let _ = write.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+/// Echoes `bytes` through each of `streams` concurrent sessions and returns
+/// the combined throughput in both directions, in MiB/s.
+pub async fn transfer_all(proxy: SocketAddr, streams: usize, bytes: usize) -> f64 {
+    let start = Instant::now();
+    let transfers: Vec<_> = (0..streams)
+        .map(|_| tokio::spawn(transfer(proxy, bytes)))
+        .collect();
+    for transfer in transfers {
+        transfer.await.unwrap().unwrap();
+    }
+    let total = (2 * streams * bytes) as f64 / (1024.0 * 1024.0);
+    total / start.elapsed().as_secs_f64()
+}
+
+async fn transfer(proxy: SocketAddr, bytes: usize) -> io::Result<()> {
+    let mut stream = TcpStream::connect(proxy).await?;
+    let (mut read, mut write) = stream.split();
+    let send = async {
+        let chunk = vec![0x5a; CHUNK];
+        let mut left = bytes;
+        while left > 0 {
+            let n = left.min(CHUNK);
+            write.write_all(&chunk[..n]).await?;
+            left -= n;
+        }
+        write.shutdown().await
+    };
+    let receive = async {
+        let mut buffer = vec![0; CHUNK];
+        let mut received = 0;
+        loop {
+            match read.read(&mut buffer).await? {
+                0 => break,
+                n => received += n,
+            }
+        }
+        if received == bytes {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "echoed {} of {} bytes",
+                received, bytes
+            )))
+        }
+    };
+    tokio::try_join!(send, receive).map(|_| ())
+}
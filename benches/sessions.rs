@@ -0,0 +1,160 @@
+//! Compares listen-mode sessions on the async bridge with the thread per
+//! direction model it replaced: threads and memory held by idle sessions,
+//! and throughput of concurrent bulk transfers.
+//!
+//! Run with `cargo bench --bench sessions`. The load is tuned with
+//! `BENCH_SESSIONS` (idle sessions, default 1000), `BENCH_STREAMS`
+//! (concurrent transfers, default 16) and `BENCH_MEGABYTES` (sent by each
+//! transfer, default 64).
+
+mod common;
+
+use common::{echo_server, env_usize, free_addr, transfer_all, wait_for_listener};
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener as StdTcpListener, TcpStream as StdTcpStream};
+use std::process::{Child, Command};
+use std::thread;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+/// Set when the benchmark re-runs itself as the threaded proxy.
+const THREADED_PROXY: &str = "BENCH_THREADED_PROXY";
+const BUFFER_SIZE: usize = 1024;
+
+#[derive(Clone, Copy)]
+enum Model {
+    Async,
+    Threaded,
+}
+
+struct Usage {
+    threads: u64,
+    rss_kib: u64,
+}
+
+fn main() {
+    if let Ok(addrs) = env::var(THREADED_PROXY) {
+        let (listen, target) = addrs.split_once(',').expect("LISTEN,TARGET");
+        threaded_proxy(listen, target);
+        return;
+    }
+
+    let sessions = env_usize("BENCH_SESSIONS", 1000);
+    let streams = env_usize("BENCH_STREAMS", 16);
+    let bytes = env_usize("BENCH_MEGABYTES", 64) * 1024 * 1024;
+
+    let runtime = Runtime::new().expect("Failed to start runtime");
+    let echo = runtime.block_on(echo_server());
+    println!(
+        "{:<9} {:>8} {:>8} {:>12} {:>12} {:>12}",
+        "model", "sessions", "threads", "rss delta", "per session", "throughput"
+    );
+    for model in [Model::Async, Model::Threaded] {
+        let listen = free_addr();
+        let mut proxy = spawn_proxy(model, listen, echo);
+        wait_for_listener(listen);
+
+        let idle = usage(&proxy);
+        let clients = runtime.block_on(open_sessions(listen, sessions));
+        let loaded = usage(&proxy);
+        drop(clients);
+        let throughput = runtime.block_on(transfer_all(listen, streams, bytes));
+
+        let rss_delta = loaded.rss_kib.saturating_sub(idle.rss_kib);
+        println!(
+            "{:<9} {:>8} {:>8} {:>8} KiB {:>8.1} KiB {:>8.1} MiB/s",
+            match model {
+                Model::Async => "async",
+                Model::Threaded => "threaded",
+            },
+            sessions,
+            loaded.threads,
+            rss_delta,
+            rss_delta as f64 / sessions as f64,
+            throughput
+        );
+        let _ = proxy.kill();
+        let _ = proxy.wait();
+    }
+}
+
+fn spawn_proxy(model: Model, listen: SocketAddr, target: SocketAddr) -> Child {
+    let mut command = match model {
+        Model::Async => {
+            let mut command = Command::new(env!("CARGO_BIN_EXE_docker-tcp"));
+            command.args(["--mode", "listen", "--log-level", "warn"]);
+            command.arg(format!("--buffer-size={}", BUFFER_SIZE));
+            command.args([listen.to_string(), target.to_string()]);
+            command
+        }
+        Model::Threaded => {
+            let mut command = Command::new(env::current_exe().expect("current exe"));
+            command.env(THREADED_PROXY, format!("{},{}", listen, target));
+            command
+        }
+    };
+    command.spawn().expect("Failed to start proxy")
+}
+
+fn usage(proxy: &Child) -> Usage {
+    let status = fs::read_to_string(format!("/proc/{}/status", proxy.id()))
+        .expect("Failed to read proxy status");
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0)
+    };
+    Usage {
+        threads: field("Threads:"),
+        rss_kib: field("VmRSS:"),
+    }
+}
+
+/// Opens `count` sessions through the proxy, round-tripping a byte on each
+/// so both of its connections are established.
+async fn open_sessions(proxy: SocketAddr, count: usize) -> Vec<TcpStream> {
+    let mut clients = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut client = TcpStream::connect(proxy).await.unwrap();
+        client.write_all(b"x").await.unwrap();
+        client.read_exact(&mut [0; 1]).await.unwrap();
+        clients.push(client);
+    }
+    clients
+}
+
+/// The previous session model: a thread per client that dials the target
+/// and waits on one thread per direction.
+fn threaded_proxy(listen: &str, target: &str) {
+    let listener = StdTcpListener::bind(listen).expect("Failed to bind");
+    for client in listener.incoming() {
+        let Ok(client) = client else { continue };
+        let target = target.to_string();
+        thread::spawn(move || {
+            let Ok(server) = StdTcpStream::connect(&target) else {
+                return;
+            };
+            let (client2, server2) = (client.try_clone().unwrap(), server.try_clone().unwrap());
+            let upstream = thread::spawn(move || forward(client, server2));
+            let downstream = thread::spawn(move || forward(server, client2));
+            let _ = upstream.join();
+            let _ = downstream.join();
+        });
+    }
+}
+
+fn forward(mut from: StdTcpStream, mut to: StdTcpStream) {
+    let mut buffer = [0; BUFFER_SIZE];
+    while let Ok(n @ 1..) = from.read(&mut buffer) {
+        if to.write_all(&buffer[..n]).is_err() {
+            break;
+        }
+    }
+    let _ = to.shutdown(Shutdown::Write);
+}
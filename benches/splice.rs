@@ -0,0 +1,49 @@
+//! Compares the throughput of the buffered copy and the splice(2) fast path
+//! on loopback, with payload logging off as the fast path requires.
+//!
+//! Run with `cargo bench --bench splice`. The load is tuned with
+//! `BENCH_STREAMS` (concurrent transfers, default 4) and `BENCH_MEGABYTES`
+//! (sent by each transfer, default 256).
+
+mod common;
+
+use common::{echo_server, env_usize, free_addr, transfer_all, wait_for_listener};
+use std::process::Command;
+use tokio::runtime::Runtime;
+
+/// Flags each path is run with, besides the endpoints.
+const PATHS: [(&str, &[&str]); 3] = [
+    ("buffered 1 KiB", &["--no-splice", "--buffer-size=1024"]),
+    ("buffered 64 KiB", &["--no-splice", "--buffer-size=65536"]),
+    ("splice", &[]),
+];
+
+fn main() {
+    let streams = env_usize("BENCH_STREAMS", 4);
+    let bytes = env_usize("BENCH_MEGABYTES", 256) * 1024 * 1024;
+
+    let runtime = Runtime::new().expect("Failed to start runtime");
+    let echo = runtime.block_on(echo_server());
+    println!("{:<16} {:>8} {:>14}", "path", "streams", "throughput");
+    for (path, flags) in PATHS {
+        let listen = free_addr();
+        let mut proxy = Command::new(env!("CARGO_BIN_EXE_docker-tcp"))
+            .args([
+                "--mode",
+                "listen",
+                "--log-level",
+                "warn",
+                "--log-payload=off",
+            ])
+            .args(flags)
+            .args([listen.to_string(), echo.to_string()])
+            .spawn()
+            .expect("Failed to start proxy");
+        wait_for_listener(listen);
+
+        let throughput = runtime.block_on(transfer_all(listen, streams, bytes));
+        println!("{:<16} {:>8} {:>8.1} MiB/s", path, streams, throughput);
+        let _ = proxy.kill();
+        let _ = proxy.wait();
+    }
+}
@@ -0,0 +1,557 @@
+use crate::bridge::{self, ActiveSession, BridgeSettings, ContainerBridge, Mode};
+use crate::config::BridgeConfig;
+use crate::http::{self, Listener, Request, Response};
+use crate::logging::PayloadLog;
+use crate::supervisor::Supervisor;
+use chrono::SecondsFormat;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, UnixListener};
+
+/// Where the admin API listens. It isn't authenticated and can make the
+/// process connect anywhere, so only loopback addresses and Unix sockets
+/// are accepted.
+#[derive(Debug, Clone)]
+pub enum AdminAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// The bridges the admin API inspects and controls: the one given on the
+/// command line, or those run by a supervisor, which can also be added and
+/// removed.
+pub enum Bridges {
+    Single(Arc<ContainerBridge>),
+    Supervised {
+        supervisor: Arc<Supervisor>,
+        /// Settings for added bridges that don't give their own.
+        defaults: Box<BridgeSettings>,
+    },
+}
+
+#[derive(Serialize)]
+struct BridgeStatus {
+    name: String,
+    mode: Mode,
+    container1: String,
+    container2: String,
+    paused: bool,
+    log_payload: String,
+    sessions: Vec<SessionStatus>,
+}
+
+/// The body of `PUT /bridges/NAME/payload`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PayloadChange {
+    log_payload: PayloadLog,
+}
+
+#[derive(Serialize)]
+struct SessionStatus {
+    id: u64,
+    started: String,
+    age_seconds: f64,
+    container1: String,
+    container2: String,
+    bytes: Bytes,
+}
+
+#[derive(Serialize)]
+struct Bytes {
+    #[serde(rename = "container1->container2")]
+    to_container2: u64,
+    #[serde(rename = "container2->container1")]
+    to_container1: u64,
+}
+
+/// Serves the admin API on the shared runtime. All bodies are JSON:
+///
+/// - `GET /bridges` lists the bridges with their sessions
+/// - `POST /bridges` adds a bridge described like one in the config file
+/// - `GET /bridges/NAME` shows one bridge, `DELETE /bridges/NAME` removes it
+/// - `GET /bridges/NAME/sessions` lists its sessions
+/// - `DELETE /bridges/NAME/sessions/ID` closes a session
+/// - `POST /bridges/NAME/pause`, `.../resume` stop and restart new sessions
+/// - `POST /bridges/NAME/reconnect` closes the sessions and redials now
+/// - `PUT /bridges/NAME/payload` sets how much forwarded data is logged,
+///   e.g. `{"log_payload": "preview:32"}`
+pub fn serve(addr: &AdminAddr, bridges: Bridges) -> io::Result<()> {
+    let runtime = bridge::runtime();
+    let listener = runtime
+        .block_on(bind(addr))
+        .map_err(|e| io::Error::new(e.kind(), format!("admin API on {}: {}", addr, e)))?;
+    info!("Serving the admin API on {}", addr);
+    runtime.spawn(http::serve(listener, move |request| {
+        bridges.handle(request)
+    }));
+    Ok(())
+}
+
+async fn bind(addr: &AdminAddr) -> io::Result<Listener> {
+    match addr {
+        AdminAddr::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+        AdminAddr::Unix(path) => {
+            // A socket left behind by an earlier run would be in the way.
+            if fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                fs::remove_file(path)?;
+            }
+            Ok(Listener::Unix(UnixListener::bind(path)?))
+        }
+    }
+}
+
+impl Bridges {
+    fn handle(&self, request: Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["bridges"]) => {
+                let statuses: Vec<_> = self.list().iter().map(|bridge| status(bridge)).collect();
+                Response::json(200, &statuses)
+            }
+            ("POST", ["bridges"]) => self.add(&request.body),
+            ("GET", ["bridges", name]) => {
+                self.with(name, |bridge| Response::json(200, &status(bridge)))
+            }
+            ("DELETE", ["bridges", name]) => self.remove(name),
+            ("GET", ["bridges", name, "sessions"]) => self.with(name, |bridge| {
+                let sessions: Vec<_> = bridge.sessions().iter().map(session_status).collect();
+                Response::json(200, &sessions)
+            }),
+            ("DELETE", ["bridges", name, "sessions", id]) => {
+                self.with(name, |bridge| match id.parse() {
+                    Ok(id) if bridge.close_session(id) => no_content(),
+                    Ok(_) => error(404, format!("no session {} on bridge `{}`", id, name)),
+                    Err(_) => error(400, format!("invalid session id `{}`", id)),
+                })
+            }
+            ("POST", ["bridges", name, action @ ("pause" | "resume" | "reconnect")]) => {
+                self.with(name, |bridge| {
+                    match *action {
+                        "pause" => bridge.pause(),
+                        "resume" => bridge.resume(),
+                        _ => {
+                            bridge.reconnect();
+                        }
+                    }
+                    Response::json(200, &status(bridge))
+                })
+            }
+            ("PUT", ["bridges", name, "payload"]) => self.with(name, |bridge| {
+                match serde_json::from_slice::<PayloadChange>(&request.body) {
+                    Ok(change) => {
+                        bridge.set_log_payload(change.log_payload);
+                        Response::json(200, &status(bridge))
+                    }
+                    Err(e) => error(400, e),
+                }
+            }),
+            (
+                _,
+                ["bridges"]
+                | ["bridges", _]
+                | ["bridges", _, "sessions" | "pause" | "resume" | "reconnect" | "payload"]
+                | ["bridges", _, "sessions", _],
+            ) => error(405, "method not allowed"),
+            _ => error(404, "not found"),
+        }
+    }
+
+    fn list(&self) -> Vec<Arc<ContainerBridge>> {
+        match self {
+            Bridges::Single(bridge) => vec![bridge.clone()],
+            Bridges::Supervised { supervisor, .. } => supervisor.bridges(),
+        }
+    }
+
+    fn with<F>(&self, name: &str, f: F) -> Response
+    where
+        F: FnOnce(&ContainerBridge) -> Response,
+    {
+        let bridge = match self {
+            Bridges::Single(bridge) => Some(bridge.clone()).filter(|bridge| bridge.name() == name),
+            Bridges::Supervised { supervisor, .. } => supervisor.get(name),
+        };
+        match bridge {
+            Some(bridge) => f(&bridge),
+            None => error(404, format!("no bridge named `{}`", name)),
+        }
+    }
+
+    fn add(&self, body: &[u8]) -> Response {
+        let Bridges::Supervised {
+            supervisor,
+            defaults,
+        } = self
+        else {
+            return fixed();
+        };
+        let config: BridgeConfig = match serde_json::from_slice(body) {
+            Ok(config) => config,
+            Err(e) => return error(400, e),
+        };
+        let bridge = match config.validate().and_then(|()| config.build(defaults)) {
+            Ok(bridge) => bridge,
+            Err(e) => return error(400, e),
+        };
+        match supervisor.add(bridge) {
+            Ok(()) => {
+                info!(bridge = config.name.as_str(); "Bridge added through the admin API");
+                self.with(&config.name, |bridge| Response::json(201, &status(bridge)))
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => error(409, e),
+            Err(e) => error(500, e),
+        }
+    }
+
+    fn remove(&self, name: &str) -> Response {
+        let Bridges::Supervised { supervisor, .. } = self else {
+            return fixed();
+        };
+        match supervisor.remove(name) {
+            true => no_content(),
+            false => error(404, format!("no bridge named `{}`", name)),
+        }
+    }
+}
+
+fn status(bridge: &ContainerBridge) -> BridgeStatus {
+    BridgeStatus {
+        name: bridge.name().to_string(),
+        mode: bridge.settings().mode,
+        container1: bridge.endpoint(0).to_string(),
+        container2: bridge.endpoint(1).to_string(),
+        paused: bridge.is_paused(),
+        log_payload: bridge.log_payload().to_string(),
+        sessions: bridge.sessions().iter().map(session_status).collect(),
+    }
+}
+
+fn session_status(session: &Arc<ActiveSession>) -> SessionStatus {
+    let [to_container2, to_container1] = session.bytes();
+    SessionStatus {
+        id: session.id,
+        started: session
+            .started
+            .to_rfc3339_opts(SecondsFormat::Millis, false),
+        age_seconds: session.age().as_secs_f64(),
+        container1: session.addrs[0].to_string(),
+        container2: session.addrs[1].to_string(),
+        bytes: Bytes {
+            to_container2,
+            to_container1,
+        },
+    }
+}
+
+fn no_content() -> Response {
+    Response {
+        status: 204,
+        content_type: "application/json",
+        body: String::new(),
+    }
+}
+
+/// The response to adding or removing a bridge given on the command line.
+fn fixed() -> Response {
+    error(
+        409,
+        "bridges can only be added or removed when run with --config, --discover or no endpoints",
+    )
+}
+
+fn error(status: u16, message: impl fmt::Display) -> Response {
+    Response::json(status, &serde_json::json!({ "error": message.to_string() }))
+}
+
+impl FromStr for AdminAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(AdminAddr::Unix(PathBuf::from(path)));
+        }
+        let addr: SocketAddr = s
+            .parse()
+            .map_err(|_| format!("expected HOST:PORT or unix:PATH, got `{}`", s))?;
+        if !addr.ip().is_loopback() {
+            return Err(format!(
+                "the admin API is unauthenticated, so it only listens on loopback \
+                 addresses or Unix sockets, not {}",
+                addr.ip()
+            ));
+        }
+        Ok(AdminAddr::Tcp(addr))
+    }
+}
+
+impl fmt::Display for AdminAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminAddr::Tcp(addr) => write!(f, "http://{}", addr),
+            AdminAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::io::{Read, Write};
+    use std::net;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn request(bridges: &Bridges, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let response = bridges.handle(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.as_bytes().to_vec(),
+        });
+        let body = match response.body.as_str() {
+            "" => Value::Null,
+            body => serde_json::from_str(body).unwrap(),
+        };
+        (response.status, body)
+    }
+
+    fn wait_for(mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "timed out waiting"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn bridges_are_added_and_removed() {
+        let supervisor = Supervisor::new(None);
+        let bridges = Bridges::Supervised {
+            supervisor: supervisor.clone(),
+            defaults: Box::default(),
+        };
+        let web = json!({
+            "name": "web",
+            "mode": "listen",
+            "container1": "127.0.0.1:0",
+            "container2": "127.0.0.1:1",
+        });
+        let (status, body) = request(&bridges, "POST", "/bridges", &web.to_string());
+        assert_eq!(status, 201);
+        assert_eq!(body["name"], "web");
+        assert_eq!(body["mode"], "listen");
+        assert_eq!(body["sessions"], json!([]));
+        assert_eq!(
+            request(&bridges, "POST", "/bridges", &web.to_string()).0,
+            409
+        );
+        let db = |field: &str, value: Value| {
+            let mut db =
+                json!({"name": "db", "container1": "127.0.0.1:1", "container2": "127.0.0.1:2"});
+            db[field] = value;
+            db.to_string()
+        };
+        for bad in [
+            "{".to_string(),
+            json!({"name": "db"}).to_string(),
+            db("buffer_size", json!(0)),
+            db("colour", json!("red")),
+        ] {
+            let (status, body) = request(&bridges, "POST", "/bridges", &bad);
+            assert_eq!(status, 400, "{}", bad);
+            assert!(body["error"].is_string());
+        }
+
+        let (status, body) = request(&bridges, "GET", "/bridges", "");
+        assert_eq!(status, 200);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(request(&bridges, "GET", "/bridges/web", "").0, 200);
+        assert_eq!(
+            request(&bridges, "DELETE", "/bridges/web", ""),
+            (204, Value::Null)
+        );
+        assert_eq!(request(&bridges, "DELETE", "/bridges/web", "").0, 404);
+        assert_eq!(request(&bridges, "GET", "/bridges/web", "").0, 404);
+        assert_eq!(request(&bridges, "GET", "/bridges", "").1, json!([]));
+        supervisor.stop();
+    }
+
+    #[test]
+    fn sessions_and_bridges_are_controlled() {
+        let upstream = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let listen = net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let supervisor = Supervisor::new(None);
+        let bridges = Bridges::Supervised {
+            supervisor: supervisor.clone(),
+            defaults: Box::default(),
+        };
+        let web = json!({
+            "name": "web",
+            "mode": "listen",
+            "container1": listen.to_string(),
+            "container2": upstream.local_addr().unwrap().to_string(),
+        });
+        assert_eq!(
+            request(&bridges, "POST", "/bridges", &web.to_string()).0,
+            201
+        );
+
+        let mut client = loop {
+            if let Ok(client) = net::TcpStream::connect(listen) {
+                break client;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let (mut server, _) = upstream.accept().unwrap();
+        client.write_all(b"ping").unwrap();
+        server.read_exact(&mut [0; 4]).unwrap();
+        let sessions = || request(&bridges, "GET", "/bridges/web/sessions", "").1;
+        wait_for(|| sessions()[0]["bytes"]["container1->container2"] == 4);
+        let id = sessions()[0]["id"].as_u64().unwrap();
+
+        assert_eq!(
+            request(&bridges, "DELETE", "/bridges/web/sessions/999", "").0,
+            404
+        );
+        assert_eq!(
+            request(&bridges, "DELETE", "/bridges/web/sessions/one", "").0,
+            400
+        );
+        let path = format!("/bridges/web/sessions/{}", id);
+        assert_eq!(request(&bridges, "DELETE", &path, ""), (204, Value::Null));
+        assert_eq!(client.read(&mut [0; 16]).unwrap(), 0);
+        wait_for(|| sessions() == json!([]));
+
+        let (status, body) = request(&bridges, "POST", "/bridges/web/pause", "");
+        assert_eq!((status, &body["paused"]), (200, &json!(true)));
+        let (status, body) = request(&bridges, "POST", "/bridges/web/resume", "");
+        assert_eq!((status, &body["paused"]), (200, &json!(false)));
+        assert_eq!(
+            request(&bridges, "POST", "/bridges/web/reconnect", "").0,
+            200
+        );
+        assert_eq!(request(&bridges, "POST", "/bridges/db/pause", "").0, 404);
+        supervisor.stop();
+    }
+
+    #[test]
+    fn rejects_wrong_methods_and_edits_of_a_single_bridge() {
+        let bridge = ContainerBridge::new(
+            "default",
+            "127.0.0.1:1".parse().unwrap(),
+            "127.0.0.1:2".parse().unwrap(),
+            BridgeSettings::default(),
+        );
+        let bridges = Bridges::Single(Arc::new(bridge));
+        for (method, path) in [
+            ("PUT", "/bridges"),
+            ("PATCH", "/bridges/default"),
+            ("GET", "/bridges/default/pause"),
+            ("POST", "/bridges/default/sessions"),
+            ("GET", "/bridges/default/sessions/1"),
+        ] {
+            assert_eq!(
+                request(&bridges, method, path, "").0,
+                405,
+                "{} {}",
+                method,
+                path
+            );
+        }
+        assert_eq!(request(&bridges, "GET", "/metrics", "").0, 404);
+        assert_eq!(request(&bridges, "GET", "/bridges/other", "").0, 404);
+
+        let (status, body) = request(&bridges, "POST", "/bridges", "{}");
+        assert_eq!(status, 409);
+        assert!(body["error"].as_str().unwrap().contains("--config"));
+        assert_eq!(request(&bridges, "DELETE", "/bridges/default", "").0, 409);
+        let (status, body) = request(&bridges, "POST", "/bridges/default/pause", "");
+        assert_eq!((status, &body["paused"]), (200, &json!(true)));
+    }
+
+    #[test]
+    fn payload_logging_is_changed() {
+        let bridge = Arc::new(ContainerBridge::new(
+            "default",
+            "127.0.0.1:1".parse().unwrap(),
+            "127.0.0.1:2".parse().unwrap(),
+            BridgeSettings::default(),
+        ));
+        let bridges = Bridges::Single(bridge.clone());
+        assert_eq!(
+            request(&bridges, "GET", "/bridges/default", "").1["log_payload"],
+            "size"
+        );
+        let change = json!({"log_payload": "preview:32"}).to_string();
+        let (status, body) = request(&bridges, "PUT", "/bridges/default/payload", &change);
+        assert_eq!((status, &body["log_payload"]), (200, &json!("preview:32")));
+        assert_eq!(bridge.log_payload(), PayloadLog::Preview(32));
+
+        for bad in [
+            json!({"log_payload": "everything"}),
+            json!({"log_payload": "preview:0"}),
+            json!({"log_payload": "full", "level": "debug"}),
+            json!({}),
+        ] {
+            let (status, body) = request(
+                &bridges,
+                "PUT",
+                "/bridges/default/payload",
+                &bad.to_string(),
+            );
+            assert_eq!(status, 400, "{}", bad);
+            assert!(body["error"].is_string());
+        }
+        assert_eq!(bridge.log_payload(), PayloadLog::Preview(32));
+        assert_eq!(
+            request(&bridges, "POST", "/bridges/default/payload", &change).0,
+            405
+        );
+        assert_eq!(
+            request(&bridges, "PUT", "/bridges/other/payload", &change).0,
+            404
+        );
+    }
+
+    #[test]
+    fn listens_on_loopback_or_unix_sockets() {
+        for addr in [
+            "127.0.0.1:9000",
+            "127.1.2.3:9000",
+            "[::1]:9000",
+            "unix:/run/admin.sock",
+        ] {
+            assert!(addr.parse::<AdminAddr>().is_ok(), "{}", addr);
+        }
+        for addr in [
+            "0.0.0.0:9000",
+            "[::]:9000",
+            "192.168.1.10:9000",
+            "[fd00::1]:9000",
+        ] {
+            let error = addr.parse::<AdminAddr>().unwrap_err();
+            assert!(
+                error.contains("only listens on loopback"),
+                "{}: {}",
+                addr,
+                error
+            );
+        }
+        assert!("localhost:9000".parse::<AdminAddr>().is_err());
+    }
+}
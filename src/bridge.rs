@@ -0,0 +1,1584 @@
+use crate::buffer::BufferPool;
+use crate::capture::Capture;
+use crate::docker::DockerClient;
+use crate::endpoint::Endpoint;
+use crate::events::{ContainerAction, ContainerEvent, EventListener};
+use crate::logging::PayloadLog;
+use crate::metrics::BridgeMetrics;
+use crate::payload::{self, PayloadFormat};
+use crate::poison;
+use crate::record::Recording;
+use crate::rendezvous::{Pairing, PendingClients};
+use crate::retry::{RetriesExhausted, RetryPolicy};
+use crate::session::{session_log, ConnectionAddrs, Direction, Session, SessionOutcome, Traffic};
+use crate::socket::SocketOptions;
+#[cfg(target_os = "linux")]
+use crate::splice::Pipe;
+use chrono::{DateTime, Local};
+use log::{debug, error, info, warn, Level};
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{self, Future};
+use std::io::{self, ErrorKind};
+use std::mem::MaybeUninit;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::{self, Runtime};
+use tokio::sync::{watch, Notify};
+use tokio::task::{self, JoinSet};
+use tokio::time;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Connect to both containers and splice the two connections together.
+    Dial,
+    /// Accept clients on the first address and connect each to the second.
+    Listen,
+    /// Accept connections on both addresses and splice them together in pairs.
+    Rendezvous,
+}
+
+/// How the two containers are dialed in `dial` mode. Whichever connection
+/// succeeds is held open while the other one is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectStrategy {
+    /// Dial both containers at once.
+    Parallel,
+    /// Dial the second container only once the first is connected.
+    Sequential,
+    /// Dial the second container only once the first has sent data.
+    Lazy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConnectOrder {
+    Container1First,
+    Container2First,
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeSettings {
+    pub mode: Mode,
+    pub retry: RetryPolicy,
+    pub connect: ConnectStrategy,
+    pub connect_order: ConnectOrder,
+    pub buffer_size: usize,
+    /// Buffers grow up to this size while reads keep filling them.
+    pub max_buffer_size: Option<usize>,
+    pub once: bool,
+    pub log_payload: PayloadLog,
+    pub payload_format: PayloadFormat,
+    /// Splice data between the sockets on Linux when payloads aren't logged,
+    /// captured or recorded.
+    pub splice: bool,
+    pub pairing: Pairing,
+    pub max_pending: usize,
+    pub docker: DockerClient,
+    pub capture: Option<Arc<Capture>>,
+    /// Directory each session is recorded to, for replaying later.
+    pub record: Option<PathBuf>,
+    /// Close sessions that forward nothing either way for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Close sessions once container1 or container2 has sent nothing for
+    /// this long, unless it has closed its side.
+    pub read_timeouts: [Option<Duration>; 2],
+    /// Close sessions once they have lasted this long.
+    pub max_lifetime: Option<Duration>,
+    /// Options for the connections to container1 and container2.
+    pub sockets: [SocketOptions; 2],
+}
+
+impl Default for BridgeSettings {
+    fn default() -> Self {
+        BridgeSettings {
+            mode: Mode::Dial,
+            retry: RetryPolicy::default(),
+            connect: ConnectStrategy::Parallel,
+            connect_order: ConnectOrder::Container1First,
+            buffer_size: 1024,
+            max_buffer_size: None,
+            once: false,
+            log_payload: PayloadLog::Size,
+            payload_format: PayloadFormat::Text,
+            splice: true,
+            pairing: Pairing::Fifo,
+            max_pending: 16,
+            docker: DockerClient::default(),
+            capture: None,
+            record: None,
+            idle_timeout: None,
+            read_timeouts: [None; 2],
+            max_lifetime: None,
+            sockets: Default::default(),
+        }
+    }
+}
+
+pub struct ContainerBridge {
+    name: String,
+    container1: Endpoint,
+    container2: Endpoint,
+    settings: BridgeSettings,
+    /// `settings.log_payload`, unless changed while running.
+    log_payload: RwLock<PayloadLog>,
+    buffers: BufferPool,
+    metrics: Arc<BridgeMetrics>,
+    wakeup: Notify,
+    sessions: Mutex<HashMap<u64, Arc<ActiveSession>>>,
+    /// Notified when the last session ends.
+    idle: Notify,
+    next_session_id: AtomicU64,
+    paused: watch::Sender<bool>,
+    stopped: watch::Sender<bool>,
+}
+
+/// A running session as seen from outside it.
+pub struct ActiveSession {
+    pub id: u64,
+    pub started: DateTime<Local>,
+    pub addrs: [ConnectionAddrs; 2],
+    bytes: Arc<[AtomicU64; 2]>,
+    clock: Instant,
+    closed: Notify,
+}
+
+impl ContainerBridge {
+    pub fn new(
+        name: impl Into<String>,
+        container1: Endpoint,
+        container2: Endpoint,
+        settings: BridgeSettings,
+    ) -> Self {
+        let name = name.into();
+        ContainerBridge {
+            metrics: BridgeMetrics::register(&name),
+            name,
+            container1,
+            container2,
+            log_payload: RwLock::new(settings.log_payload),
+            settings,
+            buffers: BufferPool::default(),
+            wakeup: Notify::new(),
+            sessions: Mutex::new(HashMap::new()),
+            idle: Notify::new(),
+            next_session_id: AtomicU64::new(1),
+            paused: watch::Sender::new(false),
+            stopped: watch::Sender::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn settings(&self) -> &BridgeSettings {
+        &self.settings
+    }
+
+    pub fn log_payload(&self) -> PayloadLog {
+        *self.log_payload.read().unwrap()
+    }
+
+    /// Changes how much forwarded data is logged. Sessions being spliced
+    /// keep logging at most sizes until they end.
+    pub fn set_log_payload(&self, mode: PayloadLog) {
+        let previous = std::mem::replace(&mut *self.log_payload.write().unwrap(), mode);
+        if previous != mode {
+            info!(bridge = self.name.as_str(); "Payload logging set to {}", mode);
+        }
+    }
+
+    /// Stops starting new sessions, leaving running ones alone: dialing
+    /// waits and accepted connections queue up until `resume`.
+    pub fn pause(&self) {
+        if !self.paused.send_replace(true) {
+            info!(bridge = self.name.as_str(); "Bridge paused");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.send_replace(false) {
+            info!(bridge = self.name.as_str(); "Bridge resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    async fn resumed(&self) {
+        let _ = self.paused.subscribe().wait_for(|paused| !*paused).await;
+    }
+
+    /// Closes every session and cuts any retry sleep short, so dial mode
+    /// connects to both containers afresh. Returns how many sessions were
+    /// closed.
+    pub fn reconnect(&self) -> usize {
+        let closed = self.close_sessions();
+        self.wakeup.notify_one();
+        info!(
+            bridge = self.name.as_str();
+            "Reconnecting, closed {} session(s)",
+            closed
+        );
+        closed
+    }
+
+    pub fn sessions(&self) -> Vec<Arc<ActiveSession>> {
+        let mut sessions: Vec<_> = poison::lock(&self.sessions).values().cloned().collect();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+
+    /// Closes one session. Returns whether it was running.
+    pub fn close_session(&self, id: u64) -> bool {
+        match poison::lock(&self.sessions).get(&id) {
+            Some(session) => {
+                session.closed.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops starting new sessions and waits for the running ones to end,
+    /// closing any left at `deadline`. Returns how many had to be closed.
+    pub async fn drain(&self, deadline: time::Instant) -> usize {
+        self.paused.send_replace(true);
+        if time::timeout_at(deadline, self.idle()).await.is_ok() {
+            return 0;
+        }
+        let closed = self.close_sessions();
+        // Give them a moment to finish their captures and recordings.
+        let _ = time::timeout(Duration::from_secs(1), self.idle()).await;
+        closed
+    }
+
+    async fn idle(&self) {
+        loop {
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if poison::lock(&self.sessions).is_empty() {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Makes `start` return as soon as possible, closing active sessions and
+    /// interrupting any retry sleep or pending accept.
+    pub fn stop(&self) {
+        self.stopped.send_replace(true);
+        self.close_sessions();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.borrow()
+    }
+
+    async fn stopped(&self) {
+        let _ = self.stopped.subscribe().wait_for(|stopped| *stopped).await;
+    }
+
+    /// Sleeps before a retry, returning early when the bridge is stopped or
+    /// a container it depends on comes up.
+    pub fn sleep(&self, duration: Duration) {
+        runtime().block_on(self.delay(duration));
+    }
+
+    async fn delay(&self, duration: Duration) {
+        tokio::select! {
+            woken = time::timeout(duration, self.wakeup.notified()) => {
+                if woken.is_ok() {
+                    info!(bridge = self.name.as_str(); "Woken up, retrying now");
+                }
+            }
+            () = self.stopped() => {}
+        }
+    }
+
+    /// Whether the bridge dials out to Docker containers, and so cares about
+    /// their lifecycle events.
+    pub fn uses_docker(&self) -> bool {
+        self.dialed_endpoints()
+            .any(|endpoint| endpoint.container().is_some())
+    }
+
+    fn dialed_endpoints(&self) -> impl Iterator<Item = &Endpoint> {
+        let container1 = match self.settings.mode {
+            Mode::Dial => Some(&self.container1),
+            Mode::Listen | Mode::Rendezvous => None,
+        };
+        let container2 = match self.settings.mode {
+            Mode::Dial | Mode::Listen => Some(&self.container2),
+            Mode::Rendezvous => None,
+        };
+        container1.into_iter().chain(container2)
+    }
+
+    /// Runs the bridge on the shared runtime until it finishes, fails or is
+    /// stopped. Sessions run as tasks, so the calling thread only drives the
+    /// dialing or accept loop.
+    pub fn start(self: &Arc<Self>) -> io::Result<()> {
+        runtime().block_on(async {
+            tokio::select! {
+                biased;
+                () = self.stopped() => Ok(()),
+                result = self.run() => result,
+            }
+        })
+    }
+
+    async fn run(self: &Arc<Self>) -> io::Result<()> {
+        match self.settings.mode {
+            Mode::Dial => self.dial().await,
+            Mode::Listen => self.listen().await,
+            Mode::Rendezvous => self.rendezvous().await,
+        }
+    }
+
+    async fn dial(&self) -> io::Result<()> {
+        info!(
+            bridge = self.name.as_str();
+            "Attempting to connect {} and {}",
+            self.container1, self.container2
+        );
+
+        let retry = &self.settings.retry;
+        let order = match self.settings.connect_order {
+            ConnectOrder::Container1First => [0, 1],
+            ConnectOrder::Container2First => [1, 0],
+        };
+        // Connections that succeeded are kept while the other side is retried.
+        let mut held: [Option<TcpStream>; 2] = [None, None];
+        let mut attempt = 0;
+        loop {
+            self.resumed().await;
+            attempt += 1;
+            for (side, stream) in held.iter_mut().enumerate() {
+                if stream.as_ref().is_some_and(is_closed) {
+                    warn!(
+                        bridge = self.name.as_str();
+                        "Held connection to container{} was closed, reconnecting",
+                        side + 1
+                    );
+                    *stream = None;
+                }
+            }
+
+            if self.settings.connect == ConnectStrategy::Parallel {
+                let [dial1, dial2] = [0, 1].map(|side| {
+                    let missing = held[side].is_none();
+                    async move {
+                        match missing {
+                            true => self.try_connect(side).await,
+                            false => None,
+                        }
+                    }
+                });
+                let (stream1, stream2) = tokio::join!(dial1, dial2);
+                for (side, stream) in [stream1, stream2].into_iter().enumerate() {
+                    if stream.is_some() {
+                        held[side] = stream;
+                    }
+                }
+            } else {
+                for (position, &side) in order.iter().enumerate() {
+                    if held[side].is_some() {
+                        continue;
+                    }
+                    if position == 1 {
+                        let Some(first) = &held[order[0]] else {
+                            break;
+                        };
+                        if self.settings.connect == ConnectStrategy::Lazy
+                            && !self.wait_for_data(order[0], first).await
+                        {
+                            held[order[0]] = None;
+                            break;
+                        }
+                    }
+                    held[side] = self.try_connect(side).await;
+                }
+            }
+
+            if held.iter().all(Option::is_some) {
+                let [stream1, stream2] = std::mem::take(&mut held).map(Option::unwrap);
+                if attempt > 1 {
+                    info!(
+                        bridge = self.name.as_str();
+                        "Connected to both containers after {} attempts!",
+                        attempt
+                    );
+                } else {
+                    info!(bridge = self.name.as_str(); "Connected to both containers!");
+                }
+                attempt = 0;
+                // A failed session only ends the bridge if it was its only one.
+                let outcome = self.handle_connection(stream1, stream2).await;
+                if self.settings.once {
+                    return outcome.into_result();
+                }
+                continue;
+            }
+
+            let waiting = match held {
+                [Some(_), None] => " (holding container1)",
+                [None, Some(_)] => " (holding container2)",
+                _ => "",
+            };
+            match retry.delay(attempt) {
+                Some(delay) => {
+                    error!(
+                        bridge = self.name.as_str();
+                        "Couldn't connect to both containers{} (attempt {}). Retrying in {:?}...",
+                        waiting, retry.attempts(attempt), delay
+                    );
+                    self.delay(delay).await;
+                }
+                None => {
+                    error!(
+                        bridge = self.name.as_str();
+                        "Couldn't connect to both containers{} (attempt {}). Giving up.",
+                        waiting, retry.attempts(attempt)
+                    );
+                    return Err(io::Error::other(RetriesExhausted {
+                        bridge: self.name.clone(),
+                        attempts: attempt,
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Dials `side`, logging why it failed.
+    async fn try_connect(&self, side: usize) -> Option<TcpStream> {
+        match self.connect(side).await {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                error!(
+                    bridge = self.name.as_str();
+                    "Couldn't connect to container{} ({}): {}",
+                    side + 1, self.endpoint(side), e
+                );
+                None
+            }
+        }
+    }
+
+    /// Container1's (`side` 0) or container2's endpoint.
+    pub fn endpoint(&self, side: usize) -> &Endpoint {
+        match side {
+            0 => &self.container1,
+            _ => &self.container2,
+        }
+    }
+
+    /// Waits until the held connection to `side` has data to read, so the
+    /// other side is only dialed once there is something to send. Returns
+    /// false if the connection was closed first.
+    async fn wait_for_data(&self, side: usize, stream: &TcpStream) -> bool {
+        debug!(
+            bridge = self.name.as_str();
+            "Waiting for data from container{} before dialing the other container",
+            side + 1
+        );
+        match stream.peek(&mut [0; 1]).await {
+            Ok(0) => {
+                warn!(
+                    bridge = self.name.as_str();
+                    "Container{} closed the connection before sending data",
+                    side + 1
+                );
+                false
+            }
+            Ok(_) => true,
+            Err(e) => {
+                warn!(bridge = self.name.as_str(); "Container{} failed: {}", side + 1, e);
+                false
+            }
+        }
+    }
+
+    async fn listen(self: &Arc<Self>) -> io::Result<()> {
+        let listener = self.bind(0).await?;
+        info!(
+            bridge = self.name.as_str();
+            "Listening on {}, forwarding clients to {}",
+            listener.local_addr()?, self.container2
+        );
+
+        // Dropping the set when the bridge stops aborts the remaining sessions.
+        let mut sessions = JoinSet::new();
+        loop {
+            let client = tokio::select! {
+                client = self.accept(0, &listener) => client,
+                Some(_) = sessions.join_next() => continue,
+            };
+            let client = match client {
+                Ok((client, _)) => client,
+                Err(e) => {
+                    error!(bridge = self.name.as_str(); "Error accepting client: {}", e);
+                    continue;
+                }
+            };
+
+            if self.settings.once {
+                self.serve_client(client).await;
+                return Ok(());
+            }
+            let bridge = Arc::clone(self);
+            sessions.spawn(async move { bridge.serve_client(client).await });
+        }
+    }
+
+    async fn serve_client(&self, client: TcpStream) {
+        let peer = describe_peer(&client);
+        info!(bridge = self.name.as_str(); "Accepted client {}", peer);
+
+        let target = match self.connect(1).await {
+            Ok(target) => target,
+            Err(e) => {
+                error!(
+                    bridge = self.name.as_str();
+                    "Couldn't connect client {} to {}: {}",
+                    peer, self.container2, e
+                );
+                return;
+            }
+        };
+
+        // The session logs its own failure.
+        let _ = self.handle_connection(client, target).await;
+    }
+
+    async fn rendezvous(self: &Arc<Self>) -> io::Result<()> {
+        let listeners = [self.bind(0).await?, self.bind(1).await?];
+        info!(
+            bridge = self.name.as_str();
+            "Waiting for connections on {} and {}",
+            listeners[0].local_addr()?, listeners[1].local_addr()?
+        );
+
+        if self.settings.once {
+            let ((client1, _), (client2, _)) =
+                tokio::try_join!(self.accept(0, &listeners[0]), self.accept(1, &listeners[1]))?;
+            info!(
+                bridge = self.name.as_str();
+                "Pairing {} with {}",
+                describe_peer(&client1), describe_peer(&client2)
+            );
+            return self.handle_connection(client1, client2).await.into_result();
+        }
+
+        let mut pending =
+            PendingClients::new(&self.name, self.settings.pairing, self.settings.max_pending);
+        let mut sessions = JoinSet::new();
+        loop {
+            let (side, client) = tokio::select! {
+                client = self.accept(0, &listeners[0]) => (0, client),
+                client = self.accept(1, &listeners[1]) => (1, client),
+                Some(_) = sessions.join_next() => continue,
+            };
+            let client = match client {
+                Ok((client, _)) => client,
+                Err(e) => {
+                    error!(bridge = self.name.as_str(); "Error accepting connection: {}", e);
+                    continue;
+                }
+            };
+            info!(
+                bridge = self.name.as_str();
+                "Accepted {} on side {}",
+                describe_peer(&client), side + 1
+            );
+            if let Some((client1, client2)) = pending.offer(side, client) {
+                let bridge = Arc::clone(self);
+                sessions.spawn(async move { bridge.serve_pair(client1, client2).await });
+            }
+        }
+    }
+
+    async fn serve_pair(&self, client1: TcpStream, client2: TcpStream) {
+        info!(
+            bridge = self.name.as_str();
+            "Pairing {} with {}",
+            describe_peer(&client1), describe_peer(&client2)
+        );
+        let _ = self.handle_connection(client1, client2).await;
+    }
+
+    /// Accepts the next connection for `side` while the bridge isn't paused.
+    async fn accept(
+        &self,
+        side: usize,
+        listener: &TcpListener,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        let mut paused = self.paused.subscribe();
+        let accepted = loop {
+            let _ = paused.wait_for(|paused| !*paused).await;
+            tokio::select! {
+                accepted = listener.accept() => break accepted?,
+                _ = paused.wait_for(|paused| *paused) => {}
+            }
+        };
+        self.settings.sockets[side].accepted(&accepted.0)?;
+        Ok(accepted)
+    }
+
+    /// Connects to container1 (`side` 0) or container2.
+    async fn connect(&self, side: usize) -> io::Result<TcpStream> {
+        let result = async {
+            let addr = self.resolve(self.endpoint(side)).await?;
+            let connect = self.settings.sockets[side].connect(addr);
+            match self.settings.retry.connect_timeouts[side] {
+                Some(timeout) => time::timeout(timeout, connect)
+                    .await
+                    .map_err(|_| io::Error::new(ErrorKind::TimedOut, "connection timed out"))?,
+                None => connect.await,
+            }
+        }
+        .await;
+        self.metrics.connected(side, result.is_ok());
+        result
+    }
+
+    /// Listens on container1's (`side` 0) or container2's endpoint.
+    async fn bind(&self, side: usize) -> io::Result<TcpListener> {
+        let addr = self.resolve(self.endpoint(side)).await?;
+        self.settings.sockets[side].listen(addr)
+    }
+
+    /// Resolving may query the Docker API or DNS, which block, so it runs
+    /// off the runtime's worker threads.
+    async fn resolve(&self, endpoint: &Endpoint) -> io::Result<SocketAddr> {
+        let endpoint = endpoint.clone();
+        let docker = self.settings.docker.clone();
+        task::spawn_blocking(move || endpoint.resolve(&docker)).await?
+    }
+
+    fn register_session(&self, session: &Session) -> RegisteredSession<'_> {
+        self.metrics.session_started();
+        let active = Arc::new(ActiveSession {
+            id: session.id,
+            started: session.started,
+            addrs: session.addrs,
+            bytes: Arc::clone(&session.bytes),
+            clock: Instant::now(),
+            closed: Notify::new(),
+        });
+        poison::lock(&self.sessions).insert(session.id, Arc::clone(&active));
+        RegisteredSession {
+            bridge: self,
+            session: active,
+        }
+    }
+
+    /// Feeds a forwarded chunk into the metrics, noting the time to the
+    /// first one in each direction.
+    fn count_forwarded(&self, session: &Session, direction: Direction, n: usize, first: &mut bool) {
+        self.metrics.forwarded(direction, n);
+        session.count(direction, n);
+        if std::mem::take(first) {
+            self.metrics.first_byte(direction, session.elapsed());
+        }
+    }
+
+    /// Ends every active session, closing both of its connections.
+    fn close_sessions(&self) -> usize {
+        let sessions = poison::lock(&self.sessions);
+        for session in sessions.values() {
+            session.closed.notify_one();
+        }
+        sessions.len()
+    }
+
+    /// Forwards between the two connections until the session ends. A panic
+    /// while forwarding only ends this session, like any other failure.
+    async fn handle_connection(
+        &self,
+        mut stream1: TcpStream,
+        mut stream2: TcpStream,
+    ) -> SessionOutcome {
+        let id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        let mut session = Session::new(id, &self.name, [&stream1, &stream2]);
+        let registered = self.register_session(&session);
+        session.capture = self
+            .settings
+            .capture
+            .as_ref()
+            .map(|capture| capture.stream(&session));
+        if let Some(dir) = &self.settings.record {
+            match Recording::create(dir, &session) {
+                Ok(recording) => session.recording = Some(recording),
+                Err(e) => session_log!(Error, session, None, "Couldn't start recording: {}", e),
+            }
+        }
+        session_log!(
+            Info,
+            session,
+            None,
+            "Session started: container1 {}, container2 {}",
+            session.addrs[0],
+            session.addrs[1]
+        );
+        let forward = self.forward(&mut stream1, &mut stream2, &session, &registered.session);
+        let outcome = catch_panic(forward).await;
+        // A failure in one direction already stopped the other. Reset both
+        // connections so neither container takes it for a clean close.
+        if outcome.is_failure() {
+            reset(&stream1);
+            reset(&stream2);
+        }
+        let level = match outcome {
+            SessionOutcome::Reset(_) => Level::Warn,
+            _ if outcome.is_failure() => Level::Error,
+            _ => Level::Info,
+        };
+        session.log(
+            level,
+            None,
+            format_args!("Session ended after {:?}: {}", session.elapsed(), outcome),
+        );
+        self.metrics.session_outcome(&outcome);
+        // A panic may have left the capture or recording mid-write.
+        if !matches!(outcome, SessionOutcome::Panicked(_)) {
+            session.finish();
+        }
+        close([stream1, stream2]);
+        outcome
+    }
+
+    async fn forward(
+        &self,
+        stream1: &mut TcpStream,
+        stream2: &mut TcpStream,
+        session: &Session,
+        active: &ActiveSession,
+    ) -> SessionOutcome {
+        let (mut read1, mut write1) = stream1.split();
+        let (mut read2, mut write2) = stream2.split();
+
+        // The session only ends once both directions have, so a half-closed
+        // side can keep receiving its peer's response. An error in either
+        // direction ends both.
+        let forward = async {
+            tokio::try_join!(
+                forward_data(
+                    &mut read1,
+                    &mut write2,
+                    session,
+                    Direction::ToContainer2,
+                    self
+                ),
+                forward_data(
+                    &mut read2,
+                    &mut write1,
+                    session,
+                    Direction::ToContainer1,
+                    self
+                ),
+            )
+            .map(|_| ())
+        };
+        let idle_timeout = self.settings.idle_timeout;
+        let max_lifetime = self.settings.max_lifetime;
+        tokio::select! {
+            result = forward => match result {
+                Ok(()) => SessionOutcome::Closed,
+                Err(e) => outcome(e),
+            },
+            () = active.closed.notified() => SessionOutcome::ClosedByBridge,
+            () = idle(session, idle_timeout.unwrap_or_default()), if idle_timeout.is_some() => {
+                SessionOutcome::TimedOut(format!("idle for {:?}", idle_timeout.unwrap_or_default()))
+            }
+            () = time::sleep(max_lifetime.unwrap_or_default()), if max_lifetime.is_some() => {
+                SessionOutcome::TimedOut(format!(
+                    "reached the maximum lifetime of {:?}",
+                    max_lifetime.unwrap_or_default()
+                ))
+            }
+        }
+    }
+}
+
+impl EventListener for ContainerBridge {
+    fn container_event(&self, event: &ContainerEvent) {
+        let dialed = self
+            .dialed_endpoints()
+            .any(|endpoint| endpoint.container() == Some(event.container.as_str()));
+        if !dialed {
+            return;
+        }
+        match event.action {
+            ContainerAction::Die => {
+                let closed = self.close_sessions();
+                info!(
+                    bridge = self.name.as_str();
+                    "Container {} died, closed {} session(s)",
+                    event.container, closed
+                );
+            }
+            ContainerAction::Start | ContainerAction::Restart | ContainerAction::Healthy => {
+                info!(bridge = self.name.as_str(); "Container {} is up", event.container);
+                self.wakeup.notify_one();
+            }
+            ContainerAction::Unhealthy => {
+                warn!(bridge = self.name.as_str(); "Container {} is unhealthy", event.container);
+            }
+        }
+    }
+}
+
+/// Why one of the bridge's timeouts closed a session.
+#[derive(Debug)]
+struct TimedOut(String);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+fn timed_out(reason: String) -> io::Error {
+    io::Error::new(ErrorKind::TimedOut, TimedOut(reason))
+}
+
+fn is_timed_out(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<TimedOut>())
+}
+
+/// Sorts a forwarding error into how the session ended.
+fn outcome(error: io::Error) -> SessionOutcome {
+    match error.kind() {
+        _ if is_timed_out(&error) => SessionOutcome::TimedOut(error.to_string()),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+            SessionOutcome::Reset(error)
+        }
+        _ => SessionOutcome::Failed(error),
+    }
+}
+
+/// Polls a session's forwarding to completion, turning a panic in any poll
+/// into the session's outcome instead of unwinding into the bridge.
+async fn catch_panic(forward: impl Future<Output = SessionOutcome>) -> SessionOutcome {
+    let mut forward = pin!(forward);
+    future::poll_fn(|cx| {
+        match panic::catch_unwind(AssertUnwindSafe(|| forward.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(panic) => Poll::Ready(SessionOutcome::Panicked(panic_message(&*panic))),
+        }
+    })
+    .await
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+/// Completes once the session has forwarded nothing for `timeout`.
+async fn idle(session: &Session, timeout: Duration) {
+    loop {
+        let idle = session.idle_for();
+        if idle >= timeout {
+            return;
+        }
+        time::sleep(timeout - idle).await;
+    }
+}
+
+/// Reads from the sender of `direction`, failing once its read timeout
+/// passes without data.
+async fn read_timeout<T>(
+    settings: &BridgeSettings,
+    direction: Direction,
+    read: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    let sender = direction.sender();
+    match settings.read_timeouts[sender] {
+        Some(timeout) => time::timeout(timeout, read).await.unwrap_or_else(|_| {
+            Err(timed_out(format!(
+                "nothing received from container{} for {:?}",
+                sender + 1,
+                timeout
+            )))
+        }),
+        None => read.await,
+    }
+}
+
+/// Removes a session from the bridge's registry when it ends.
+struct RegisteredSession<'a> {
+    bridge: &'a ContainerBridge,
+    session: Arc<ActiveSession>,
+}
+
+impl Drop for RegisteredSession<'_> {
+    fn drop(&mut self) {
+        let mut sessions = poison::lock(&self.bridge.sessions);
+        sessions.remove(&self.session.id);
+        if sessions.is_empty() {
+            self.bridge.idle.notify_waiters();
+        }
+        drop(sessions);
+        self.bridge.metrics.session_ended(self.session.age());
+    }
+}
+
+impl ActiveSession {
+    pub fn age(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    /// Bytes forwarded from container1 and from container2 so far.
+    pub fn bytes(&self) -> [u64; 2] {
+        [0, 1].map(|sender| self.bytes[sender].load(Ordering::Relaxed))
+    }
+}
+
+/// The runtime shared by all bridges. Its worker pool defaults to one
+/// thread per CPU and can be sized with `TOKIO_WORKER_THREADS`.
+pub fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("session-worker")
+            .build()
+            .expect("Failed to start the async runtime")
+    })
+}
+
+/// Whether the peer of an idle connection has already gone away.
+pub fn is_closed(stream: &TcpStream) -> bool {
+    match SockRef::from(stream).peek(&mut [MaybeUninit::uninit(); 1]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != ErrorKind::WouldBlock,
+    }
+}
+
+pub fn describe_peer(stream: &TcpStream) -> String {
+    stream
+        .peer_addr()
+        .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string())
+}
+
+async fn forward_data(
+    from: &mut ReadHalf<'_>,
+    to: &mut WriteHalf<'_>,
+    session: &Session,
+    direction: Direction,
+    bridge: &ContainerBridge,
+) -> io::Result<()> {
+    let settings = &bridge.settings;
+    let observed = direction;
+    let direction = Some(direction);
+    #[cfg(target_os = "linux")]
+    if settings.splice && !bridge.log_payload().logs_data() && !session.is_observed() {
+        match Pipe::new() {
+            Ok(pipe) => return splice_data(from, to, session, observed, bridge, pipe).await,
+            Err(e) => session_log!(
+                Warn,
+                session,
+                direction,
+                "Can't splice, copying through a buffer instead: {}",
+                e
+            ),
+        }
+    }
+
+    let mut buffer = bridge.buffers.take(settings.buffer_size);
+    let mut first = true;
+    loop {
+        match read_timeout(settings, observed, from.read(&mut buffer)).await {
+            Ok(0) => break,
+            Ok(n) => {
+                let full = n == buffer.len();
+                let data = &buffer[..n];
+                bridge.count_forwarded(session, observed, n, &mut first);
+                payload::log(
+                    session,
+                    direction,
+                    data,
+                    bridge.log_payload(),
+                    settings.payload_format,
+                );
+                session.observe(observed, Traffic::Data(data));
+
+                if let Err(e) = to.write_all(data).await {
+                    session_log!(Error, session, direction, "Error writing data: {}", e);
+                    reset(from.as_ref());
+                    session.observe(observed, Traffic::Reset { by_sender: false });
+                    return Err(e);
+                }
+
+                // A full read suggests more is waiting, so take more at once.
+                if let Some(max) = settings.max_buffer_size.filter(|&max| full && n < max) {
+                    let size = n.saturating_mul(2).min(max);
+                    session_log!(
+                        Debug,
+                        session,
+                        direction,
+                        "Growing buffer to {} bytes",
+                        size
+                    );
+                    buffer = bridge.buffers.take(size);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if is_timed_out(&e) => return Err(e),
+            Err(e) => {
+                session_log!(Error, session, direction, "Error reading data: {}", e);
+                reset(to.as_ref());
+                session.observe(observed, Traffic::Reset { by_sender: true });
+                return Err(e);
+            }
+        }
+    }
+    session.observe(observed, Traffic::Closed);
+    half_close(to, session, direction).await;
+    Ok(())
+}
+
+/// Like the buffered loop in `forward_data`, but the data only passes
+/// through a pipe in the kernel.
+#[cfg(target_os = "linux")]
+async fn splice_data(
+    from: &mut ReadHalf<'_>,
+    to: &mut WriteHalf<'_>,
+    session: &Session,
+    observed: Direction,
+    bridge: &ContainerBridge,
+    mut pipe: Pipe,
+) -> io::Result<()> {
+    let direction = Some(observed);
+    let mut first = true;
+    loop {
+        match read_timeout(&bridge.settings, observed, pipe.fill(from.as_ref())).await {
+            Ok(0) => break,
+            Ok(n) => {
+                bridge.count_forwarded(session, observed, n, &mut first);
+                if bridge.log_payload() != PayloadLog::Off {
+                    session.log_bytes(direction, n);
+                }
+                if let Err(e) = pipe.drain(to.as_ref()).await {
+                    session_log!(Error, session, direction, "Error writing data: {}", e);
+                    reset(from.as_ref());
+                    return Err(e);
+                }
+            }
+            Err(e) if is_timed_out(&e) => return Err(e),
+            Err(e) => {
+                session_log!(Error, session, direction, "Error reading data: {}", e);
+                reset(to.as_ref());
+                return Err(e);
+            }
+        }
+    }
+    half_close(to, session, direction).await;
+    Ok(())
+}
+
+async fn half_close(to: &mut WriteHalf<'_>, session: &Session, direction: Option<Direction>) {
+    session_log!(Info, session, direction, "Connection closed.");
+
+    // Pass the FIN on so the peer sees end-of-stream but can still reply.
+    if let Err(e) = to.shutdown().await {
+        session_log!(Debug, session, direction, "Couldn't half-close: {}", e);
+    }
+}
+
+/// Closes a session's connections. With a nonzero SO_LINGER, closing blocks
+/// until unsent data is acknowledged or the linger time is up, so lingering
+/// sockets are closed off the runtime's worker threads.
+fn close(streams: [TcpStream; 2]) {
+    let lingers = |stream: &TcpStream| {
+        SockRef::from(stream)
+            .linger()
+            .is_ok_and(|linger| linger.is_some_and(|linger| !linger.is_zero()))
+    };
+    if streams.iter().any(lingers) {
+        let streams = streams.map(TcpStream::into_std);
+        task::spawn_blocking(move || drop(streams));
+    }
+}
+
+/// Passes a reset on to the peer of `stream`: once the session drops the
+/// socket it is closed with an RST instead of a FIN.
+fn reset(stream: &TcpStream) {
+    let _ = SockRef::from(stream).set_linger(Some(Duration::ZERO));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::fake::{event, reply, FakeDocker};
+    use crate::events::DockerEvents;
+    use std::io::{Read, Write};
+    use std::net::{self, Shutdown};
+    use std::sync::mpsc;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A bridge in listen mode on loopback, forwarding to `upstream`.
+    struct Running {
+        bridge: Arc<ContainerBridge>,
+        addr: SocketAddr,
+        thread: Option<thread::JoinHandle<io::Result<()>>>,
+    }
+
+    impl Running {
+        fn listen(upstream: &net::TcpListener, settings: BridgeSettings) -> Self {
+            let upstream = upstream.local_addr().unwrap();
+            Running::forward_to(Endpoint::Host(upstream.to_string()), settings)
+        }
+
+        fn forward_to(container2: Endpoint, settings: BridgeSettings) -> Self {
+            // The listener is dropped right away, so the bridge can take its port.
+            let addr = net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let bridge = Arc::new(ContainerBridge::new(
+                "test",
+                Endpoint::Host(addr.to_string()),
+                container2,
+                BridgeSettings {
+                    mode: Mode::Listen,
+                    ..settings
+                },
+            ));
+            let thread = thread::spawn({
+                let bridge = Arc::clone(&bridge);
+                move || bridge.start()
+            });
+            Running {
+                bridge,
+                addr,
+                thread: Some(thread),
+            }
+        }
+
+        fn connect(&self) -> net::TcpStream {
+            let stream = wait_for(|| net::TcpStream::connect(self.addr).ok());
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            stream
+        }
+    }
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            self.bridge.stop();
+            let result = self.thread.take().unwrap().join();
+            if !thread::panicking() {
+                result.unwrap().unwrap();
+            }
+        }
+    }
+
+    fn upstream() -> net::TcpListener {
+        net::TcpListener::bind("127.0.0.1:0").unwrap()
+    }
+
+    fn accept(listener: &net::TcpListener) -> net::TcpStream {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        stream
+    }
+
+    /// Polls `f` until it returns something, failing the test after `TIMEOUT`.
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = f() {
+                return value;
+            }
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn read_all(stream: &mut net::TcpStream) -> Vec<u8> {
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    fn half_closed_client_receives_reply(splice: bool) {
+        let upstream = upstream();
+        let running = Running::listen(
+            &upstream,
+            BridgeSettings {
+                splice,
+                ..Default::default()
+            },
+        );
+        let mut client = running.connect();
+        client.write_all(b"ping").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let mut server = accept(&upstream);
+        assert_eq!(read_all(&mut server), b"ping");
+        server.write_all(b"pong").unwrap();
+        drop(server);
+        assert_eq!(read_all(&mut client), b"pong");
+    }
+
+    #[test]
+    fn half_closed_client_receives_reply_buffered() {
+        half_closed_client_receives_reply(false);
+    }
+
+    #[test]
+    fn half_closed_client_receives_reply_spliced() {
+        half_closed_client_receives_reply(true);
+    }
+
+    #[test]
+    fn server_reset_reaches_client() {
+        let upstream = upstream();
+        let running = Running::listen(&upstream, BridgeSettings::default());
+        let mut client = running.connect();
+        client.write_all(b"hello").unwrap();
+
+        let mut server = accept(&upstream);
+        server.read_exact(&mut [0; 5]).unwrap();
+        SockRef::from(&server)
+            .set_linger(Some(Duration::ZERO))
+            .unwrap();
+        drop(server);
+
+        let error = client.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn session_ends_once_both_directions_have() {
+        let upstream = upstream();
+        let running = Running::listen(&upstream, BridgeSettings::default());
+        let mut client = running.connect();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let mut server = accept(&upstream);
+        assert_eq!(read_all(&mut server), b"");
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(running.bridge.sessions().len(), 1);
+
+        server.write_all(b"late").unwrap();
+        drop(server);
+        assert_eq!(read_all(&mut client), b"late");
+        wait_for(|| running.bridge.sessions().is_empty().then_some(()));
+    }
+
+    /// A bridge listening for clients of the `db` container, which the fake
+    /// Docker API places on loopback in front of an upstream listener.
+    struct DockerBridge {
+        running: Running,
+        /// Streams Docker events to the bridge.
+        events: mpsc::Sender<String>,
+        _watcher: Arc<DockerEvents>,
+        _docker: FakeDocker,
+    }
+
+    fn docker_bridge(upstream: &net::TcpListener) -> DockerBridge {
+        let port = upstream.local_addr().unwrap().port();
+        let db = r#"{"Name": "/db", "NetworkSettings": {"Networks": {"test": {"IPAddress": "127.0.0.1"}}}}"#;
+        let (docker, events) = FakeDocker::with_events(&[("db", db)]);
+        let running = Running::forward_to(
+            format!("docker://db:{}", port).parse().unwrap(),
+            BridgeSettings {
+                docker: docker.client.clone(),
+                ..Default::default()
+            },
+        );
+        let watcher = DockerEvents::spawn(docker.client.clone()).unwrap();
+        let listener: Arc<dyn EventListener> = running.bridge.clone();
+        watcher.subscribe(Arc::downgrade(&listener));
+        DockerBridge {
+            running,
+            events,
+            _watcher: watcher,
+            _docker: docker,
+        }
+    }
+
+    #[test]
+    fn container_death_closes_its_sessions() {
+        let upstream = upstream();
+        let DockerBridge {
+            running, events, ..
+        } = &docker_bridge(&upstream);
+        let mut client = running.connect();
+        let _server = accept(&upstream);
+        wait_for(|| (running.bridge.sessions().len() == 1).then_some(()));
+
+        events.send(event("die", "cache")).unwrap();
+        events.send(event("start", "db")).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(running.bridge.sessions().len(), 1);
+
+        events.send(event("die", "db")).unwrap();
+        wait_for(|| running.bridge.sessions().is_empty().then_some(()));
+        assert_eq!(read_all(&mut client), b"");
+    }
+
+    #[test]
+    fn container_coming_up_cuts_the_retry_sleep_short() {
+        let upstream = upstream();
+        let DockerBridge {
+            running, events, ..
+        } = &docker_bridge(&upstream);
+        for action in ["start", "health_status: healthy"] {
+            let bridge = Arc::clone(&running.bridge);
+            let sleeper = thread::spawn(move || bridge.sleep(Duration::from_secs(60)));
+
+            events.send(event(action, "cache")).unwrap();
+            events
+                .send(event("health_status: unhealthy", "db"))
+                .unwrap();
+            thread::sleep(Duration::from_millis(200));
+            assert!(!sleeper.is_finished(), "woken by an unrelated event");
+
+            events.send(event(action, "db")).unwrap();
+            wait_for(|| sleeper.is_finished().then_some(()));
+        }
+    }
+
+    #[test]
+    fn parallel_dials_both_containers_at_once() {
+        // Looking container1 up takes long enough to tell the dials apart.
+        let docker = FakeDocker::serve(|_, mut stream| {
+            thread::sleep(Duration::from_secs(2));
+            reply(
+                &mut stream,
+                404,
+                r#"{"message": "No such container"}"#,
+                false,
+            );
+        });
+        let upstream = upstream();
+        upstream.set_nonblocking(true).unwrap();
+        let bridge = Arc::new(ContainerBridge::new(
+            "test",
+            "docker://slow:80".parse().unwrap(),
+            Endpoint::Host(upstream.local_addr().unwrap().to_string()),
+            BridgeSettings {
+                docker: docker.client.clone(),
+                ..Default::default()
+            },
+        ));
+        let start = Instant::now();
+        let thread = thread::spawn({
+            let bridge = Arc::clone(&bridge);
+            move || bridge.start()
+        });
+
+        while upstream.accept().is_err() {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "container2 wasn't dialed until container1 was"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        bridge.stop();
+        thread.join().unwrap().unwrap();
+    }
+
+    /// Runs a session between two loopback connection pairs while `drive`
+    /// uses their client ends, which stay open until the session is over.
+    fn run_session<F, T>(
+        settings: BridgeSettings,
+        drive: impl FnOnce(TcpStream, TcpStream) -> F,
+    ) -> (SessionOutcome, Duration)
+    where
+        F: Future<Output = T>,
+    {
+        let bridge = ContainerBridge::new(
+            "test",
+            Endpoint::Host("127.0.0.1:1".to_string()),
+            Endpoint::Host("127.0.0.1:2".to_string()),
+            settings,
+        );
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let pair = || async {
+                let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+                (client.unwrap(), accepted.unwrap().0)
+            };
+            let (client1, stream1) = pair().await;
+            let (client2, stream2) = pair().await;
+            let start = Instant::now();
+            let (outcome, _clients) = tokio::join!(
+                bridge.handle_connection(stream1, stream2),
+                drive(client1, client2)
+            );
+            (outcome, start.elapsed())
+        })
+    }
+
+    fn assert_timed_out(outcome: SessionOutcome, reason: &str) {
+        match outcome {
+            SessionOutcome::TimedOut(timed_out) => assert_eq!(timed_out, reason),
+            outcome => panic!("session ended with {:?}", outcome),
+        }
+    }
+
+    const SHORT: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn idle_sessions_time_out() {
+        let settings = BridgeSettings {
+            idle_timeout: Some(SHORT),
+            ..Default::default()
+        };
+        let (outcome, elapsed) = run_session(settings, |mut client1, client2| async move {
+            // Traffic either way keeps the session going.
+            for _ in 0..3 {
+                client1.write_all(b"ping").await.unwrap();
+                time::sleep(SHORT / 2).await;
+            }
+            (client1, client2)
+        });
+        assert_timed_out(outcome, "idle for 200ms");
+        // The last write came after `SHORT`, and held the timeout off.
+        assert!(elapsed >= SHORT * 2, "{:?}", elapsed);
+    }
+
+    #[test]
+    fn silent_container_times_out_reading() {
+        let settings = BridgeSettings {
+            read_timeouts: [None, Some(SHORT)],
+            ..Default::default()
+        };
+        let (outcome, elapsed) = run_session(settings, |mut client1, client2| async move {
+            // Only container2's silence counts.
+            for _ in 0..10 {
+                if client1.write_all(b"ping").await.is_err() {
+                    break;
+                }
+                time::sleep(SHORT / 4).await;
+            }
+            (client1, client2)
+        });
+        assert_timed_out(outcome, "nothing received from container2 for 200ms");
+        assert!(elapsed >= SHORT && elapsed < SHORT * 5, "{:?}", elapsed);
+    }
+
+    #[test]
+    fn read_timeout_spares_a_half_closed_direction() {
+        let settings = BridgeSettings {
+            read_timeouts: [None, Some(SHORT)],
+            ..Default::default()
+        };
+        let (outcome, elapsed) = run_session(settings, |mut client1, mut client2| async move {
+            client2.shutdown().await.unwrap();
+            time::sleep(SHORT * 3).await;
+            client1.write_all(b"late").await.unwrap();
+            client1.shutdown().await.unwrap();
+            let mut late = Vec::new();
+            client2.read_to_end(&mut late).await.unwrap();
+            assert_eq!(late, b"late");
+            (client1, client2)
+        });
+        assert!(matches!(outcome, SessionOutcome::Closed), "{:?}", outcome);
+        assert!(elapsed >= SHORT * 3, "{:?}", elapsed);
+    }
+
+    #[test]
+    fn sessions_end_at_their_maximum_lifetime() {
+        let settings = BridgeSettings {
+            max_lifetime: Some(SHORT),
+            idle_timeout: Some(SHORT * 10),
+            ..Default::default()
+        };
+        let (outcome, elapsed) = run_session(settings, |mut client1, client2| async move {
+            for _ in 0..20 {
+                if client1.write_all(b"ping").await.is_err() {
+                    break;
+                }
+                time::sleep(SHORT / 4).await;
+            }
+            (client1, client2)
+        });
+        assert_timed_out(outcome, "reached the maximum lifetime of 200ms");
+        assert!(elapsed >= SHORT && elapsed < SHORT * 5, "{:?}", elapsed);
+    }
+
+    #[test]
+    fn panics_become_the_session_outcome() {
+        let outcomes = runtime().block_on(async {
+            [
+                catch_panic(async { SessionOutcome::Closed }).await,
+                catch_panic(async { panic!("panicked at once") }).await,
+                // Panics in later polls are caught too.
+                catch_panic(async {
+                    time::sleep(Duration::from_millis(10)).await;
+                    panic!("panicked after {:?}", Duration::from_millis(10))
+                })
+                .await,
+            ]
+        });
+        assert!(matches!(outcomes[0], SessionOutcome::Closed));
+        for (outcome, expected) in outcomes[1..]
+            .iter()
+            .zip(["panicked at once", "panicked after 10ms"])
+        {
+            match outcome {
+                SessionOutcome::Panicked(message) => assert_eq!(message, expected),
+                outcome => panic!("session ended with {:?}", outcome),
+            }
+        }
+    }
+
+    #[test]
+    fn bridge_keeps_accepting_after_a_panic_holding_its_sessions() {
+        let upstream = upstream();
+        let running = Running::listen(&upstream, BridgeSettings::default());
+        let bridge = &running.bridge;
+        thread::scope(|scope| {
+            let panicked = scope.spawn(|| {
+                let _sessions = bridge.sessions.lock().unwrap();
+                panic!("panicking with the sessions held");
+            });
+            assert!(panicked.join().is_err());
+        });
+        assert!(bridge.sessions.is_poisoned());
+
+        let mut client = running.connect();
+        let mut server = accept(&upstream);
+        wait_for(|| (bridge.sessions().len() == 1).then_some(()));
+        assert!(!bridge.close_session(u64::MAX));
+        client.write_all(b"ping").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(read_all(&mut server), b"ping");
+        server.write_all(b"pong").unwrap();
+        drop(server);
+        assert_eq!(read_all(&mut client), b"pong");
+        wait_for(|| bridge.sessions().is_empty().then_some(()));
+    }
+}
@@ -0,0 +1,83 @@
+use crate::poison;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// Most memory a pool keeps in idle buffers.
+const MAX_POOLED_BYTES: usize = 16 * 1024 * 1024;
+
+/// Forwarding buffers kept for reuse once their session ends, so sessions
+/// coming and going don't allocate afresh each time. A session panicking
+/// while holding the pool leaves it consistent, so the pool stays usable.
+#[derive(Default)]
+pub struct BufferPool {
+    idle: Mutex<Idle>,
+}
+
+#[derive(Default)]
+struct Idle {
+    buffers: HashMap<usize, Vec<Vec<u8>>>,
+    bytes: usize,
+}
+
+impl BufferPool {
+    /// Takes a buffer of `size` bytes, which goes back to the pool when dropped.
+    pub fn take(&self, size: usize) -> Buffer<'_> {
+        let mut idle = poison::lock(&self.idle);
+        let idle = &mut *idle;
+        let data = match idle.buffers.get_mut(&size).and_then(Vec::pop) {
+            Some(data) => {
+                idle.bytes -= size;
+                data
+            }
+            None => vec![0; size],
+        };
+        Buffer { pool: self, data }
+    }
+
+    fn put_back(&self, data: Vec<u8>) {
+        let mut idle = poison::lock(&self.idle);
+        if idle.bytes + data.len() <= MAX_POOLED_BYTES {
+            idle.bytes += data.len();
+            idle.buffers.entry(data.len()).or_default().push(data);
+        }
+    }
+}
+
+pub struct Buffer<'a> {
+    pool: &'a BufferPool,
+    data: Vec<u8>,
+}
+
+impl Deref for Buffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for Buffer<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Drop for Buffer<'_> {
+    fn drop(&mut self) {
+        self.pool.put_back(std::mem::take(&mut self.data));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = BufferPool::default();
+        let address = pool.take(64).as_ptr();
+        assert_eq!(pool.take(64).as_ptr(), address);
+        assert_eq!(pool.take(128).len(), 128);
+    }
+}
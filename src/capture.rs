@@ -0,0 +1,622 @@
+use crate::logging;
+use crate::poison;
+use crate::session::{Direction, Session};
+use log::{error, info};
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// LINKTYPE_RAW: packets start with their IPv4 or IPv6 header.
+const LINKTYPE_RAW: u16 = 101;
+/// Largest TCP payload that fits an IPv4 packet; bigger chunks are split.
+const MAX_SEGMENT: usize = 65_535 - 20 - 20;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+/// A pcapng file that forwarded traffic is written to, one synthesized TCP
+/// connection per session between container1's and container2's peers, so
+/// Wireshark can dissect it like the real thing.
+#[derive(Debug)]
+pub struct Capture {
+    path: PathBuf,
+    /// Start a new file once the current one would grow past this size.
+    max_size: Option<u64>,
+    /// Start a new file once the current one is this old.
+    interval: Option<Duration>,
+    max_files: usize,
+    /// None once writing has failed.
+    file: Mutex<Option<CaptureFile>>,
+}
+
+#[derive(Debug)]
+struct CaptureFile {
+    file: File,
+    size: u64,
+    /// Size of the headers, so a file is only rotated once it has packets.
+    header_size: u64,
+    opened: Instant,
+}
+
+impl Capture {
+    pub fn open(
+        path: PathBuf,
+        max_size: Option<u64>,
+        interval: Option<Duration>,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        // A capture left by an earlier run is kept like a rotated one.
+        let file = match path.exists() {
+            true => logging::rotate(&path, max_files)?,
+            false => File::create(&path)?,
+        };
+        let file = CaptureFile::start(file)?;
+        Ok(Capture {
+            path,
+            max_size,
+            interval,
+            max_files,
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// Starts the synthesized connection for `session` with a handshake.
+    pub fn stream(self: &Arc<Self>, session: &Session) -> CaptureStream {
+        let unspecified = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+        let mut addrs = session.addrs.map(|addrs| addrs.peer.unwrap_or(unspecified));
+        // Both ends need the same IP version.
+        if addrs.iter().any(SocketAddr::is_ipv6) {
+            addrs = addrs.map(|addr| match addr.ip() {
+                IpAddr::V4(ip) => SocketAddr::new(ip.to_ipv6_mapped().into(), addr.port()),
+                IpAddr::V6(_) => addr,
+            });
+        }
+        let isn = (session.id as u32).wrapping_mul(0x9e37_79b9);
+        let stream = CaptureStream {
+            capture: self.clone(),
+            addrs,
+            next_seq: [AtomicU32::new(isn), AtomicU32::new(!isn)],
+            closed: [AtomicBool::new(false), AtomicBool::new(false)],
+        };
+        let comment = format!("{} session {}", session.bridge, session.id);
+        stream.send(0, SYN, &[], Some(&comment));
+        stream.send(1, SYN | ACK, &[], None);
+        stream.send(0, ACK, &[], None);
+        stream
+    }
+
+    fn write(&self, block: &[u8]) {
+        let mut file = poison::lock_file(&self.file, &self.path);
+        let Some(current) = file.as_mut() else {
+            return;
+        };
+        let result = self.rotate_if_due(current, block.len()).and_then(|()| {
+            current.file.write_all(block)?;
+            current.size += block.len() as u64;
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!(
+                "Couldn't write capture {}, stopping it: {}",
+                self.path.display(),
+                e
+            );
+            *file = None;
+        }
+    }
+
+    fn rotate_if_due(&self, current: &mut CaptureFile, length: usize) -> io::Result<()> {
+        let full = self.max_size.is_some_and(|max_size| {
+            current.size > current.header_size && current.size + length as u64 > max_size
+        });
+        let old = self
+            .interval
+            .is_some_and(|interval| current.opened.elapsed() >= interval);
+        if full || old {
+            *current = CaptureFile::start(logging::rotate(&self.path, self.max_files)?)?;
+            info!("Started a new capture file {}", self.path.display());
+        }
+        Ok(())
+    }
+}
+
+impl CaptureFile {
+    /// Writes the headers every pcapng file starts with.
+    fn start(mut file: File) -> io::Result<Self> {
+        // Section header: byte-order magic, version 1.0, unknown length.
+        let mut header = Vec::new();
+        header.extend(0x1a2b_3c4d_u32.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend((-1i64).to_le_bytes());
+        let mut blocks = block(0x0a0d_0d0a, &header);
+
+        // Interface description: raw IP packets, no snapshot length limit.
+        let mut interface = Vec::new();
+        interface.extend(LINKTYPE_RAW.to_le_bytes());
+        interface.extend(0u16.to_le_bytes());
+        interface.extend(0u32.to_le_bytes());
+        blocks.extend(block(1, &interface));
+
+        file.write_all(&blocks)?;
+        Ok(CaptureFile {
+            file,
+            size: blocks.len() as u64,
+            header_size: blocks.len() as u64,
+            opened: Instant::now(),
+        })
+    }
+}
+
+/// One session's synthesized TCP connection. Side 0 is container1's peer
+/// and side 1 container2's.
+#[derive(Debug)]
+pub struct CaptureStream {
+    capture: Arc<Capture>,
+    addrs: [SocketAddr; 2],
+    next_seq: [AtomicU32; 2],
+    closed: [AtomicBool; 2],
+}
+
+impl CaptureStream {
+    pub fn direction(&self, direction: Direction) -> CaptureDirection<'_> {
+        CaptureDirection {
+            stream: self,
+            side: direction.sender(),
+        }
+    }
+
+    /// Closes any side still open once the session is over.
+    pub fn finish(&self) {
+        for side in 0..2 {
+            self.close(side, FIN | ACK);
+        }
+    }
+
+    fn close(&self, side: usize, flags: u8) {
+        if !self.closed[side].swap(true, Ordering::Relaxed) {
+            self.send(side, flags, &[], None);
+        }
+    }
+
+    fn send(&self, side: usize, flags: u8, payload: &[u8], comment: Option<&str>) {
+        let mut length = payload.len() as u32;
+        if flags & (SYN | FIN) != 0 {
+            length += 1;
+        }
+        let seq = self.next_seq[side].fetch_add(length, Ordering::Relaxed);
+        let ack = match flags & ACK {
+            0 => 0,
+            _ => self.next_seq[1 - side].load(Ordering::Relaxed),
+        };
+        let packet = packet(
+            self.addrs[side],
+            self.addrs[1 - side],
+            seq,
+            ack,
+            flags,
+            payload,
+        );
+        self.capture.write(&enhanced_packet(&packet, comment));
+    }
+}
+
+/// The data a session forwards in one direction, sent by `side`.
+pub struct CaptureDirection<'a> {
+    stream: &'a CaptureStream,
+    side: usize,
+}
+
+impl CaptureDirection<'_> {
+    pub fn data(&self, data: &[u8]) {
+        for segment in data.chunks(MAX_SEGMENT) {
+            self.stream.send(self.side, PSH | ACK, segment, None);
+        }
+    }
+
+    /// Records the sender closing its side.
+    pub fn fin(&self) {
+        self.stream.close(self.side, FIN | ACK);
+    }
+
+    /// Records the connection being reset by the sender, or otherwise by the
+    /// receiver.
+    pub fn reset(&self, by_sender: bool) {
+        let side = if by_sender { self.side } else { 1 - self.side };
+        self.stream.close(side, RST | ACK);
+    }
+}
+
+/// Builds an IP packet carrying a TCP segment.
+fn packet(
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut tcp = Vec::with_capacity(20 + payload.len());
+    tcp.extend(src.port().to_be_bytes());
+    tcp.extend(dst.port().to_be_bytes());
+    tcp.extend(seq.to_be_bytes());
+    tcp.extend(ack.to_be_bytes());
+    tcp.extend([5 << 4, flags]);
+    tcp.extend(u16::MAX.to_be_bytes());
+    tcp.extend([0; 4]);
+    tcp.extend(payload);
+
+    let mut packet = Vec::with_capacity(40 + tcp.len());
+    let mut pseudo_header = Vec::new();
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet.extend([0x45, 0]);
+            packet.extend(((20 + tcp.len()) as u16).to_be_bytes());
+            // No identification, don't fragment, TTL 64, TCP.
+            packet.extend([0, 0, 0x40, 0, 64, 6, 0, 0]);
+            packet.extend(src.octets());
+            packet.extend(dst.octets());
+            let checksum = checksum(&packet, 0);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            pseudo_header.extend(src.octets());
+            pseudo_header.extend(dst.octets());
+            pseudo_header.extend([0, 6]);
+            pseudo_header.extend((tcp.len() as u16).to_be_bytes());
+        }
+        (src, dst) => {
+            let ipv6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            packet.extend([0x60, 0, 0, 0]);
+            packet.extend((tcp.len() as u16).to_be_bytes());
+            // TCP, hop limit 64.
+            packet.extend([6, 64]);
+            packet.extend(ipv6(src).octets());
+            packet.extend(ipv6(dst).octets());
+
+            pseudo_header.extend(ipv6(src).octets());
+            pseudo_header.extend(ipv6(dst).octets());
+            pseudo_header.extend((tcp.len() as u32).to_be_bytes());
+            pseudo_header.extend([0, 0, 0, 6]);
+        }
+    }
+    let checksum = checksum(&tcp, sum(&pseudo_header));
+    tcp[16..18].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend(tcp);
+    packet
+}
+
+fn sum(data: &[u8]) -> u32 {
+    data.chunks(2)
+        .map(|pair| u32::from(pair[0]) << 8 | u32::from(pair.get(1).copied().unwrap_or(0)))
+        .fold(0, u32::wrapping_add)
+}
+
+/// The internet checksum of `data`, starting from the partial sum `initial`.
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = u64::from(initial) + u64::from(sum(data));
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// An enhanced packet block holding `packet`, timestamped now.
+fn enhanced_packet(packet: &[u8], comment: Option<&str>) -> Vec<u8> {
+    let micros = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    let mut body = Vec::with_capacity(20 + packet.len() + 3);
+    body.extend(0u32.to_le_bytes());
+    body.extend(((micros >> 32) as u32).to_le_bytes());
+    body.extend((micros as u32).to_le_bytes());
+    body.extend((packet.len() as u32).to_le_bytes());
+    body.extend((packet.len() as u32).to_le_bytes());
+    body.extend(packet);
+    pad(&mut body);
+    if let Some(comment) = comment {
+        body.extend(1u16.to_le_bytes());
+        body.extend((comment.len() as u16).to_le_bytes());
+        body.extend(comment.as_bytes());
+        pad(&mut body);
+        body.extend([0; 4]);
+    }
+    block(6, &body)
+}
+
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let length = (12 + body.len()) as u32;
+    let mut block = Vec::with_capacity(length as usize);
+    block.extend(block_type.to_le_bytes());
+    block.extend(length.to_le_bytes());
+    block.extend(body);
+    block.extend(length.to_le_bytes());
+    block
+}
+
+fn pad(data: &mut Vec<u8>) {
+    data.resize(data.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge;
+    use crate::session::ConnectionAddrs;
+    use std::path::Path;
+    use std::{env, fs, process, thread};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// A session between two made-up peers.
+    fn session(peers: [SocketAddr; 2]) -> Session {
+        let (stream1, stream2) = bridge::runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (stream1, stream2) = tokio::join!(TcpStream::connect(addr), listener.accept());
+            (stream1.unwrap(), stream2.unwrap().0)
+        });
+        let mut session = Session::new(7, "test", [&stream1, &stream2]);
+        session.addrs = peers.map(|peer| ConnectionAddrs {
+            local: None,
+            peer: Some(peer),
+        });
+        session
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("docker-tcp-{}-{}.pcapng", name, process::id()))
+    }
+
+    /// Splits a pcapng file into its blocks' types and bodies, checking the
+    /// lengths framing each block.
+    fn blocks(path: &Path) -> Vec<(u32, Vec<u8>)> {
+        let file = fs::read(path).unwrap();
+        let u32_at = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap());
+        let mut blocks = Vec::new();
+        let mut at = 0;
+        while at < file.len() {
+            let length = u32_at(at + 4) as usize;
+            assert_eq!(length % 4, 0, "block at {} isn't padded", at);
+            assert_eq!(u32_at(at + length - 4) as usize, length);
+            blocks.push((u32_at(at), file[at + 8..at + length - 4].to_vec()));
+            at += length;
+        }
+        assert_eq!(at, file.len());
+        blocks
+    }
+
+    /// The ones' complement sum of `data` as 16-bit words, which is 0xffff
+    /// when `data` holds its own valid checksum.
+    fn ones_complement_sum(data: &[u8]) -> u16 {
+        let mut sum = 0u32;
+        for word in data.chunks(2) {
+            sum += u32::from(u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]));
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        sum as u16
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Segment {
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        payload: Vec<u8>,
+    }
+
+    /// Decodes an IP packet, checking its checksums.
+    fn segment(packet: &[u8]) -> Segment {
+        let (src, dst, tcp, mut pseudo_header): (IpAddr, IpAddr, _, Vec<u8>) = match packet[0] >> 4
+        {
+            4 => {
+                assert_eq!(ones_complement_sum(&packet[..20]), 0xffff, "IPv4 checksum");
+                let length = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+                assert_eq!(length, packet.len());
+                let src: [u8; 4] = packet[12..16].try_into().unwrap();
+                let dst: [u8; 4] = packet[16..20].try_into().unwrap();
+                let tcp = &packet[20..];
+                let mut pseudo_header = [&src[..], &dst, &[0, 6]].concat();
+                pseudo_header.extend((tcp.len() as u16).to_be_bytes());
+                (src.into(), dst.into(), tcp, pseudo_header)
+            }
+            6 => {
+                let length = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+                assert_eq!(40 + length, packet.len());
+                let src: [u8; 16] = packet[8..24].try_into().unwrap();
+                let dst: [u8; 16] = packet[24..40].try_into().unwrap();
+                let tcp = &packet[40..];
+                let mut pseudo_header = [&src[..], &dst].concat();
+                pseudo_header.extend((tcp.len() as u32).to_be_bytes());
+                pseudo_header.extend([0, 0, 0, 6]);
+                (src.into(), dst.into(), tcp, pseudo_header)
+            }
+            version => panic!("IP version {}", version),
+        };
+        pseudo_header.extend(tcp);
+        assert_eq!(ones_complement_sum(&pseudo_header), 0xffff, "TCP checksum");
+        let u16_at = |at: usize| u16::from_be_bytes([tcp[at], tcp[at + 1]]);
+        let u32_at = |at: usize| u32::from_be_bytes(tcp[at..at + 4].try_into().unwrap());
+        Segment {
+            src: SocketAddr::new(src, u16_at(0)),
+            dst: SocketAddr::new(dst, u16_at(2)),
+            seq: u32_at(4),
+            ack: u32_at(8),
+            flags: tcp[13],
+            payload: tcp[(tcp[12] >> 4) as usize * 4..].to_vec(),
+        }
+    }
+
+    /// The packets of a capture, with the comments on them.
+    fn packets(path: &Path) -> Vec<(Segment, Option<String>)> {
+        let blocks = blocks(path);
+        let (section, body) = &blocks[0];
+        assert_eq!(*section, 0x0a0d_0d0a);
+        assert_eq!(body[..4], 0x1a2b_3c4d_u32.to_le_bytes());
+        let (interface, body) = &blocks[1];
+        assert_eq!(*interface, 1);
+        assert_eq!(body[..2], LINKTYPE_RAW.to_le_bytes());
+
+        let u32_at =
+            |body: &[u8], at: usize| u32::from_le_bytes(body[at..at + 4].try_into().unwrap());
+        blocks[2..]
+            .iter()
+            .map(|(kind, body)| {
+                assert_eq!(*kind, 6);
+                let length = u32_at(body, 12) as usize;
+                assert_eq!(u32_at(body, 16) as usize, length);
+                let options = 20 + length.next_multiple_of(4);
+                assert!(body[20 + length..options].iter().all(|&byte| byte == 0));
+                let comment = (options < body.len()).then(|| {
+                    assert_eq!(body[options..options + 2], 1u16.to_le_bytes());
+                    let length = u16::from_le_bytes([body[options + 2], body[options + 3]]);
+                    let comment = &body[options + 4..options + 4 + length as usize];
+                    assert_eq!(
+                        body.len() - 4,
+                        (options + 4 + length as usize).next_multiple_of(4)
+                    );
+                    assert_eq!(body[body.len() - 4..], [0; 4], "end of options");
+                    String::from_utf8(comment.to_vec()).unwrap()
+                });
+                (segment(&body[20..20 + length]), comment)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sessions_are_captured_as_tcp_connections() {
+        let path = temp_path("session");
+        let capture = Arc::new(Capture::open(path.clone(), None, None, 0).unwrap());
+        let peers: [SocketAddr; 2] = [
+            "10.0.0.1:40000".parse().unwrap(),
+            "10.0.0.2:6379".parse().unwrap(),
+        ];
+        let stream = capture.stream(&session(peers));
+        stream.direction(Direction::ToContainer2).data(b"hello");
+        stream.direction(Direction::ToContainer1).data(b"hi");
+        stream.direction(Direction::ToContainer2).fin();
+        stream.direction(Direction::ToContainer1).reset(true);
+        stream.finish();
+
+        let packets = packets(&path);
+        let isn = [packets[0].0.seq, packets[1].0.seq];
+        // Each side's sequence numbers relative to its initial one.
+        let segment = |side: usize, seq: u32, ack: u32, flags: u8, payload: &[u8]| Segment {
+            src: peers[side],
+            dst: peers[1 - side],
+            seq: isn[side].wrapping_add(seq),
+            ack: match flags & ACK {
+                0 => 0,
+                _ => isn[1 - side].wrapping_add(ack),
+            },
+            flags,
+            payload: payload.to_vec(),
+        };
+        let expected = [
+            segment(0, 0, 0, SYN, b""),
+            segment(1, 0, 1, SYN | ACK, b""),
+            segment(0, 1, 1, ACK, b""),
+            segment(0, 1, 1, PSH | ACK, b"hello"),
+            segment(1, 1, 6, PSH | ACK, b"hi"),
+            segment(0, 6, 3, FIN | ACK, b""),
+            segment(1, 3, 7, RST | ACK, b""),
+        ];
+        let segments: Vec<_> = packets.iter().map(|(segment, _)| segment).collect();
+        assert_eq!(segments, expected.iter().collect::<Vec<_>>());
+        let comments: Vec<_> = packets
+            .iter()
+            .map(|(_, comment)| comment.as_deref())
+            .collect();
+        assert_eq!(comments[0], Some("test session 7"));
+        assert!(comments[1..].iter().all(Option::is_none));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mixed_ip_versions_are_captured_as_ipv6() {
+        let path = temp_path("ipv6");
+        let capture = Arc::new(Capture::open(path.clone(), None, None, 0).unwrap());
+        let peers: [SocketAddr; 2] = [
+            "[fd00::1]:40000".parse().unwrap(),
+            "10.0.0.2:6379".parse().unwrap(),
+        ];
+        let stream = capture.stream(&session(peers));
+        stream.direction(Direction::ToContainer1).data(b"odd");
+        stream.finish();
+
+        let packets = packets(&path);
+        assert_eq!(packets.len(), 6);
+        let (data, _) = &packets[3];
+        assert_eq!(data.src.to_string(), "[::ffff:10.0.0.2]:6379");
+        assert_eq!(data.dst, peers[0]);
+        assert_eq!(data.payload, b"odd");
+        assert!(packets.iter().all(|(segment, _)| segment.src.is_ipv6()));
+        assert_eq!(packets[4].0.flags, FIN | ACK);
+        assert_eq!(packets[5].0.dst, peers[0]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn earlier_captures_are_rotated_aside() {
+        let dir = env::temp_dir().join(format!("docker-tcp-capture-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("traffic.pcapng");
+        let rotated = |n: usize| dir.join(format!("traffic.pcapng.{}", n));
+        fs::write(&path, "first run").unwrap();
+
+        drop(Capture::open(path.clone(), None, None, 2).unwrap());
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "first run");
+        drop(Capture::open(path.clone(), None, None, 2).unwrap());
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap(), "first run");
+        assert!(!rotated(3).exists());
+        assert!(fs::metadata(&path).unwrap().len() > 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn captures_rotate_by_size_and_interval() {
+        let dir = env::temp_dir().join(format!("docker-tcp-capture-rotation-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("traffic.pcapng");
+        let rotated = dir.join("traffic.pcapng.1");
+        let size = |path: &Path| fs::metadata(path).unwrap().len();
+        let headers = 28 + 20;
+
+        let capture = Capture::open(path.clone(), Some(headers + 100), None, 1).unwrap();
+        // A file with only headers takes any packet, however big.
+        capture.write(&[0; 120]);
+        assert!(!rotated.exists());
+        capture.write(&[0; 40]);
+        assert_eq!(size(&rotated), headers + 120);
+        assert_eq!(size(&path), headers + 40);
+        capture.write(&[0; 60]);
+        assert_eq!(size(&path), headers + 100);
+        capture.write(&[0; 4]);
+        assert_eq!(size(&rotated), headers + 100);
+        assert_eq!(size(&path), headers + 4);
+        drop(capture);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+        let interval = Duration::from_millis(200);
+        let capture = Capture::open(path.clone(), None, Some(interval), 1).unwrap();
+        capture.write(&[0; 8]);
+        assert!(!rotated.exists());
+        thread::sleep(interval);
+        capture.write(&[0; 4]);
+        assert_eq!(size(&rotated), headers + 8);
+        assert_eq!(size(&path), headers + 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,441 @@
+use crate::admin::AdminAddr;
+use crate::bridge::{BridgeSettings, ConnectOrder, ConnectStrategy, Mode};
+use crate::docker::DockerClient;
+use crate::endpoint::Endpoint;
+use crate::logging::{LogFormat, PayloadLog};
+use crate::payload::PayloadFormat;
+use crate::rendezvous::Pairing;
+use crate::replay::ReplayArgs;
+use crate::retry::{Backoff, RetryPolicy};
+use crate::socket::SocketOptions;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use log::LevelFilter;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Bridge TCP traffic between two containers.
+///
+/// When no endpoints are given and stdin is a terminal, the addresses are
+/// prompted for interactively.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The two containers to bridge, as HOST:PORT or docker://CONTAINER:PORT
+    /// (e.g. 127.0.0.1:3000 docker://my-redis:6379?network=backend)
+    #[arg(value_name = "ENDPOINT")]
+    pub endpoints: Vec<Endpoint>,
+
+    /// TOML or YAML file describing the bridges to run; other options act as defaults
+    #[arg(short, long, value_name = "PATH", conflicts_with = "endpoints")]
+    pub config: Option<PathBuf>,
+
+    /// How the endpoints are used: `dial` connects to both, `listen` accepts
+    /// clients on the first and connects each of them to the second,
+    /// `rendezvous` accepts on both and pairs the connections up
+    #[arg(long, value_enum, default_value_t = Mode::Dial)]
+    pub mode: Mode,
+
+    /// How the two containers are dialed in dial mode; a successful
+    /// connection is held while the other container is retried
+    #[arg(long, value_enum, default_value_t = ConnectStrategy::Parallel)]
+    pub connect: ConnectStrategy,
+
+    /// Which container is dialed first by the sequential and lazy strategies
+    #[arg(long, value_enum, default_value_t = ConnectOrder::Container1First)]
+    pub connect_order: ConnectOrder,
+
+    /// Which waiting connection a rendezvous partner is paired with
+    #[arg(long, value_enum, default_value_t = Pairing::Fifo)]
+    pub pairing: Pairing,
+
+    /// Maximum connections waiting for a partner on each rendezvous side
+    #[arg(long, value_name = "COUNT", default_value = "16", value_parser = parse_max_pending)]
+    pub max_pending: usize,
+
+    /// Docker Engine API socket used to resolve docker:// endpoints
+    /// [default: $DOCKER_HOST or /var/run/docker.sock]
+    #[arg(long, value_name = "PATH")]
+    pub docker_socket: Option<PathBuf>,
+
+    /// Create bridges from the `tcp-bridge.*` labels of running containers
+    #[arg(long, conflicts_with = "endpoints")]
+    pub discover: bool,
+
+    /// Seconds between rescans of container labels when discovering bridges
+    #[arg(long, value_name = "SECS", default_value = "30", value_parser = parse_seconds)]
+    pub discovery_interval: Duration,
+
+    /// Don't follow Docker container events to reconnect as soon as a
+    /// container comes back
+    #[arg(long)]
+    pub no_docker_events: bool,
+
+    /// Seconds to wait before retrying when a container can't be reached
+    /// (the first delay with exponential backoff)
+    #[arg(long, value_name = "SECS", default_value = "5", value_parser = parse_seconds)]
+    pub retry_interval: Duration,
+
+    /// How the delay between connection attempts grows
+    #[arg(long, value_enum, default_value_t = Backoff::Fixed)]
+    pub backoff: Backoff,
+
+    /// Upper bound in seconds for exponential backoff delays
+    #[arg(long, value_name = "SECS", default_value = "60", value_parser = parse_seconds)]
+    pub max_retry_interval: Duration,
+
+    /// Factor the delay is multiplied by after each failed attempt with exponential backoff
+    #[arg(long, value_name = "FACTOR", default_value = "2")]
+    pub backoff_multiplier: f64,
+
+    /// Fraction (0 to 1) of each retry delay to randomise
+    #[arg(long, value_name = "FRACTION", default_value = "0")]
+    pub retry_jitter: f64,
+
+    /// Give up and exit with status 3 after this many failed connection attempts
+    #[arg(long, value_name = "COUNT")]
+    pub max_attempts: Option<u32>,
+
+    /// Seconds to wait for each connection to a container before failing the attempt
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub connect_timeout: Option<Duration>,
+
+    /// Close sessions that forward nothing either way for this many seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub idle_timeout: Option<Duration>,
+
+    /// Close sessions once either container has sent nothing for this many
+    /// seconds, unless it has closed its side
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub read_timeout: Option<Duration>,
+
+    /// Close sessions once they have lasted this many seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub max_lifetime: Option<Duration>,
+
+    /// Disable Nagle's algorithm (TCP_NODELAY) on the connections to both
+    /// containers
+    #[arg(long)]
+    pub nodelay: bool,
+
+    /// Turn on TCP keepalive, probing after this many idle seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub keepalive: Option<Duration>,
+
+    /// Seconds between TCP keepalive probes
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub keepalive_interval: Option<Duration>,
+
+    /// Unanswered TCP keepalive probes before a connection is dropped
+    #[arg(long, value_name = "COUNT")]
+    pub keepalive_count: Option<u32>,
+
+    /// Socket send buffer size in bytes (SO_SNDBUF)
+    #[arg(long, value_name = "BYTES")]
+    pub send_buffer_size: Option<usize>,
+
+    /// Socket receive buffer size in bytes (SO_RCVBUF)
+    #[arg(long, value_name = "BYTES")]
+    pub recv_buffer_size: Option<usize>,
+
+    /// Seconds closing a connection waits for unsent data (SO_LINGER)
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub linger: Option<Duration>,
+
+    /// Seconds sent data may go unacknowledged before a connection is
+    /// dropped (TCP_USER_TIMEOUT)
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub user_timeout: Option<Duration>,
+
+    /// Source address to dial containers from
+    #[arg(long, value_name = "IP")]
+    pub bind_address: Option<IpAddr>,
+
+    /// Network interface to bind connections to (SO_BINDTODEVICE)
+    #[arg(long, value_name = "NAME")]
+    pub interface: Option<String>,
+
+    /// Minimum level of log messages to print (off, error, warn, info, debug, trace)
+    /// [default: $RUST_LOG or info]. SIGUSR1 and SIGUSR2 raise and lower it
+    /// while running
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Format of log lines
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Write logs to this file instead of stderr
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it reaches this size in bytes
+    #[arg(long, value_name = "BYTES", default_value = "10485760", value_parser = clap::value_parser!(u64).range(1..))]
+    pub log_max_size: u64,
+
+    /// Number of rotated log files to keep (FILE.1 being the newest)
+    #[arg(long, value_name = "COUNT", default_value = "5")]
+    pub log_max_files: usize,
+
+    /// Size in bytes of the buffer used to forward data
+    #[arg(long, value_name = "BYTES", default_value = "1024", value_parser = parse_buffer_size)]
+    pub buffer_size: usize,
+
+    /// Let buffers grow up to this size in bytes while reads keep filling them
+    #[arg(long, value_name = "BYTES", value_parser = parse_buffer_size)]
+    pub max_buffer_size: Option<usize>,
+
+    /// How much of the forwarded data to log: off, size, preview[:BYTES]
+    /// (the first 64 or BYTES bytes of each chunk) or full. SIGHUP re-reads
+    /// the modes of bridges from the config file; the admin API's
+    /// `PUT /bridges/NAME/payload` changes any bridge's
+    #[arg(long, value_name = "MODE", default_value_t = PayloadLog::Size)]
+    pub log_payload: PayloadLog,
+
+    /// How logged data is shown: as text, as an xxd-style hexdump, or as
+    /// printable text with everything else escaped
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = PayloadFormat::Text)]
+    pub payload_format: PayloadFormat,
+
+    /// Always copy data through the buffer, even where it could be spliced
+    /// between the sockets in the kernel (Linux, logging at most sizes)
+    #[arg(long)]
+    pub no_splice: bool,
+
+    /// Write forwarded traffic to this pcapng file, each session as a TCP
+    /// connection between the containers' peers (disables splicing)
+    #[arg(long, value_name = "PATH")]
+    pub capture: Option<PathBuf>,
+
+    /// Start a new capture file once it reaches this size in bytes
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    pub capture_max_size: Option<u64>,
+
+    /// Start a new capture file after this many seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub capture_interval: Option<Duration>,
+
+    /// Number of previous capture files to keep (FILE.1 being the newest)
+    #[arg(long, value_name = "COUNT", default_value = "5")]
+    pub capture_max_files: usize,
+
+    /// Record each session to DIR/BRIDGE-STARTED-SESSION.jsonl, to be
+    /// replayed with `replay` (disables splicing)
+    #[arg(long, value_name = "DIR")]
+    pub record: Option<PathBuf>,
+
+    /// Serve Prometheus metrics at http://ADDR/metrics (e.g. 127.0.0.1:9100)
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Serve the admin API on a loopback HOST:PORT or unix:PATH; it isn't
+    /// authenticated. Without endpoints, bridges are added through it
+    #[arg(long, value_name = "ADDR")]
+    pub admin_listen: Option<AdminAddr>,
+
+    /// Seconds sessions get to finish on SIGTERM or SIGINT before they are
+    /// closed
+    #[arg(long, value_name = "SECS", default_value = "30", value_parser = parse_seconds)]
+    pub drain_timeout: Duration,
+
+    /// Exit after the first session ends instead of reconnecting
+    #[arg(long)]
+    pub once: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Replay one side of a session recorded with --record against a live
+    /// container, reporting where its responses differ from the recording
+    Replay(ReplayArgs),
+}
+
+impl Cli {
+    /// Parses the command line, exiting with a usage error if it is invalid.
+    pub fn parse_args() -> Self {
+        Cli::parse().validate().unwrap_or_else(|e| e.exit())
+    }
+
+    /// Checks what clap can't check one argument at a time.
+    fn validate(self) -> Result<Self, clap::Error> {
+        if !self.endpoints.is_empty() && self.endpoints.len() != 2 {
+            return Err(Cli::command().error(
+                ErrorKind::WrongNumberOfValues,
+                format!("expected exactly 2 endpoints, got {}", self.endpoints.len()),
+            ));
+        }
+        if let Err(e) = self.retry_policy().validate() {
+            return Err(Cli::command().error(ErrorKind::ValueValidation, e));
+        }
+        if self
+            .max_buffer_size
+            .is_some_and(|max| max < self.buffer_size)
+        {
+            return Err(Cli::command().error(
+                ErrorKind::ValueValidation,
+                "--max-buffer-size must be at least --buffer-size",
+            ));
+        }
+        Ok(self)
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            backoff: self.backoff,
+            interval: self.retry_interval,
+            max_interval: self.max_retry_interval,
+            multiplier: self.backoff_multiplier,
+            jitter: self.retry_jitter,
+            max_attempts: self.max_attempts,
+            connect_timeouts: [self.connect_timeout; 2],
+        }
+    }
+
+    pub fn settings(&self) -> BridgeSettings {
+        BridgeSettings {
+            mode: self.mode,
+            retry: self.retry_policy(),
+            connect: self.connect,
+            connect_order: self.connect_order,
+            buffer_size: self.buffer_size,
+            max_buffer_size: self.max_buffer_size,
+            once: self.once,
+            log_payload: self.log_payload,
+            payload_format: self.payload_format,
+            splice: !self.no_splice,
+            pairing: self.pairing,
+            max_pending: self.max_pending,
+            docker: self
+                .docker_socket
+                .as_ref()
+                .map_or_else(DockerClient::default, DockerClient::new),
+            capture: None,
+            record: self.record.clone(),
+            idle_timeout: self.idle_timeout,
+            read_timeouts: [self.read_timeout; 2],
+            max_lifetime: self.max_lifetime,
+            sockets: [(); 2].map(|()| self.socket_options()),
+        }
+    }
+
+    fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            nodelay: self.nodelay.then_some(true),
+            keepalive: self.keepalive,
+            keepalive_interval: self.keepalive_interval,
+            keepalive_count: self.keepalive_count,
+            send_buffer_size: self.send_buffer_size,
+            recv_buffer_size: self.recv_buffer_size,
+            linger: self.linger,
+            user_timeout: self.user_timeout,
+            bind: self.bind_address,
+            interface: self.interface.clone(),
+        }
+    }
+
+    /// Exits with a usage error when endpoints are required but missing.
+    pub fn missing_endpoints(reason: &str) -> ! {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                format!("no endpoints given and {}", reason),
+            )
+            .exit()
+    }
+}
+
+pub fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+}
+
+fn parse_max_pending(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("at least one pending connection must be allowed".to_string()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("`{}` is not a valid count", s)),
+    }
+}
+
+fn parse_buffer_size(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("buffer size must be greater than zero".to_string()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("`{}` is not a valid size", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["docker-tcp"].iter().chain(args)).and_then(Cli::validate)
+    }
+
+    fn assert_rejected(args: &[&str], kind: ErrorKind) {
+        match parse(args) {
+            Ok(_) => panic!("{:?} was accepted", args),
+            Err(e) => assert_eq!(e.kind(), kind, "{:?}: {}", args, e),
+        }
+    }
+
+    #[test]
+    fn endpoints_come_in_pairs() {
+        assert!(parse(&[]).unwrap().endpoints.is_empty());
+        let cli = parse(&["127.0.0.1:3000", "docker://redis:6379"]).unwrap();
+        assert_eq!(cli.endpoints.len(), 2);
+
+        assert_rejected(&["127.0.0.1:3000"], ErrorKind::WrongNumberOfValues);
+        assert_rejected(
+            &["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"],
+            ErrorKind::WrongNumberOfValues,
+        );
+        assert_rejected(&["127.0.0.1", "127.0.0.1:2"], ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn rejects_conflicting_modes() {
+        let endpoints = ["127.0.0.1:1", "127.0.0.1:2"];
+        for flags in [&["--config", "bridges.toml"][..], &["--discover"]] {
+            let args = [flags, &endpoints].concat();
+            assert_rejected(&args, ErrorKind::ArgumentConflict);
+            assert!(parse(flags).is_ok(), "{:?}", flags);
+        }
+        assert_rejected(&["--mode", "broadcast"], ErrorKind::InvalidValue);
+        let cli = parse(&["replay", "session.jsonl", "127.0.0.1:1"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Replay(_))));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for args in [
+            &["--retry-interval", "soon"][..],
+            &["--retry-interval=-1"],
+            &["--retry-interval", "0"],
+            &["--idle-timeout", "1e30"],
+            &["--retry-jitter", "1.5"],
+            &["--backoff-multiplier", "0.5"],
+            &["--max-attempts", "0"],
+            &["--connect-timeout", "0"],
+            &["--buffer-size", "0"],
+            &["--buffer-size", "big"],
+            &["--max-buffer-size", "512"],
+            &["--buffer-size", "8192", "--max-buffer-size", "4096"],
+            &["--max-pending", "0"],
+            &["--max-pending=-3"],
+        ] {
+            assert_rejected(args, ErrorKind::ValueValidation);
+        }
+
+        let cli = parse(&["--buffer-size", "4096", "--max-buffer-size", "4096"]).unwrap();
+        assert_eq!((cli.buffer_size, cli.max_buffer_size), (4096, Some(4096)));
+        let cli = parse(&["--retry-interval", "0.25", "--max-pending", "1"]).unwrap();
+        assert_eq!(cli.retry_interval, Duration::from_millis(250));
+        assert_eq!(cli.max_pending, 1);
+    }
+}
@@ -0,0 +1,483 @@
+use crate::bridge::{BridgeSettings, ConnectOrder, ConnectStrategy, ContainerBridge, Mode};
+use crate::endpoint::Endpoint;
+use crate::invalid_data;
+use crate::logging::PayloadLog;
+use crate::payload::PayloadFormat;
+use crate::rendezvous::Pairing;
+use crate::retry::{Backoff, RetryPolicy};
+use crate::socket::SocketOptions;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Bridges loaded from a TOML or YAML file, e.g.
+///
+/// ```toml
+/// [[bridges]]
+/// name = "postgres"
+/// container1 = "127.0.0.1:5432"
+/// container2 = "127.0.0.1:6432"
+/// connect = "lazy"
+/// buffer_size = 8192
+/// max_buffer_size = 262144
+/// idle_timeout = 300
+/// container2_read_timeout = 30
+/// socket = { nodelay = true, keepalive = 60, keepalive_interval = 10 }
+/// container2_socket = { bind = "10.0.0.5", user_timeout = 30 }
+/// log_payload = "preview:128"
+/// payload_format = "hex"
+/// retry = { backoff = "exponential", interval = 0.5, max_interval = 30, jitter = 0.2 }
+///
+/// [[bridges]]
+/// name = "web"
+/// mode = "listen"
+/// container1 = "0.0.0.0:8080"
+/// container2 = "docker://web:80?network=frontend"
+///
+/// [[bridges]]
+/// name = "agents"
+/// mode = "rendezvous"
+/// container1 = "0.0.0.0:9001"
+/// container2 = "0.0.0.0:9002"
+/// pairing = "fifo"
+/// max_pending = 4
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub bridges: Vec<BridgeConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeConfig {
+    pub name: String,
+    pub container1: Endpoint,
+    pub container2: Endpoint,
+    pub mode: Option<Mode>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    pub connect: Option<ConnectStrategy>,
+    pub connect_order: Option<ConnectOrder>,
+    pub buffer_size: Option<usize>,
+    pub max_buffer_size: Option<usize>,
+    pub once: Option<bool>,
+    pub log_payload: Option<PayloadLog>,
+    pub payload_format: Option<PayloadFormat>,
+    pub splice: Option<bool>,
+    pub pairing: Option<Pairing>,
+    pub max_pending: Option<usize>,
+    /// Set to false to leave the bridge out of `--capture`.
+    pub capture: Option<bool>,
+    /// Directory the bridge's sessions are recorded to.
+    pub record: Option<PathBuf>,
+    /// Session timeouts, in seconds.
+    pub idle_timeout: Option<f64>,
+    pub read_timeout: Option<f64>,
+    pub container1_read_timeout: Option<f64>,
+    pub container2_read_timeout: Option<f64>,
+    pub max_lifetime: Option<f64>,
+    /// Socket options for both connections, and for each of them.
+    #[serde(default)]
+    pub socket: SocketConfig,
+    #[serde(default)]
+    pub container1_socket: SocketConfig,
+    #[serde(default)]
+    pub container2_socket: SocketConfig,
+}
+
+/// Durations are in seconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    pub backoff: Option<Backoff>,
+    pub interval: Option<f64>,
+    pub max_interval: Option<f64>,
+    pub multiplier: Option<f64>,
+    pub jitter: Option<f64>,
+    pub max_attempts: Option<u32>,
+    pub connect_timeout: Option<f64>,
+    pub container1_connect_timeout: Option<f64>,
+    pub container2_connect_timeout: Option<f64>,
+}
+
+/// Durations are in seconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocketConfig {
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<f64>,
+    pub keepalive_interval: Option<f64>,
+    pub keepalive_count: Option<u32>,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    pub linger: Option<f64>,
+    pub user_timeout: Option<f64>,
+    pub bind: Option<IpAddr>,
+    pub interface: Option<String>,
+}
+
+impl SocketConfig {
+    fn build(&self) -> Result<SocketOptions, String> {
+        Ok(SocketOptions {
+            nodelay: self.nodelay,
+            keepalive: seconds(self.keepalive)?,
+            keepalive_interval: seconds(self.keepalive_interval)?,
+            keepalive_count: self.keepalive_count,
+            send_buffer_size: self.send_buffer_size,
+            recv_buffer_size: self.recv_buffer_size,
+            linger: seconds(self.linger)?,
+            user_timeout: seconds(self.user_timeout)?,
+            bind: self.bind,
+            interface: self.interface.clone(),
+        })
+    }
+}
+
+impl RetryConfig {
+    fn build(&self, defaults: &RetryPolicy) -> Result<RetryPolicy, String> {
+        let connect_timeout = seconds(self.connect_timeout)?;
+        let policy = RetryPolicy {
+            backoff: self.backoff.unwrap_or(defaults.backoff),
+            interval: seconds(self.interval)?.unwrap_or(defaults.interval),
+            max_interval: seconds(self.max_interval)?.unwrap_or(defaults.max_interval),
+            multiplier: self.multiplier.unwrap_or(defaults.multiplier),
+            jitter: self.jitter.unwrap_or(defaults.jitter),
+            max_attempts: self.max_attempts.or(defaults.max_attempts),
+            connect_timeouts: [
+                seconds(self.container1_connect_timeout)?
+                    .or(connect_timeout)
+                    .or(defaults.connect_timeouts[0]),
+                seconds(self.container2_connect_timeout)?
+                    .or(connect_timeout)
+                    .or(defaults.connect_timeouts[1]),
+            ],
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Config> {
+        let contents = fs::read_to_string(path)?;
+        let config: Config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&contents).map_err(invalid_data)?,
+            _ => toml::from_str(&contents).map_err(invalid_data)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        if self.bridges.is_empty() {
+            return Err(invalid_data("no bridges configured"));
+        }
+        let mut names = HashSet::new();
+        for bridge in &self.bridges {
+            bridge.validate()?;
+            if !names.insert(bridge.name.as_str()) {
+                return Err(invalid_data(format!(
+                    "duplicate bridge name `{}`",
+                    bridge.name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BridgeConfig {
+    pub fn validate(&self) -> io::Result<()> {
+        if self.name.is_empty() {
+            return Err(invalid_data("bridge names must not be empty"));
+        }
+        if self.buffer_size == Some(0) {
+            return Err(invalid_data(format!(
+                "{}: buffer_size must be greater than zero",
+                self.name
+            )));
+        }
+        if self.max_pending == Some(0) {
+            return Err(invalid_data(format!(
+                "{}: max_pending must be greater than zero",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Builds the bridge, taking any setting not given in the file from `defaults`.
+    pub fn build(&self, defaults: &BridgeSettings) -> io::Result<ContainerBridge> {
+        let retry = self
+            .retry
+            .build(&defaults.retry)
+            .map_err(|e| invalid_data(format!("{}: retry: {}", self.name, e)))?;
+        let seconds = |field, value| {
+            seconds(value).map_err(|e| invalid_data(format!("{}: {}: {}", self.name, field, e)))
+        };
+        let read_timeout = seconds("read_timeout", self.read_timeout)?;
+        let socket = |field, config: &SocketConfig| {
+            config
+                .build()
+                .map_err(|e| invalid_data(format!("{}: {}: {}", self.name, field, e)))
+        };
+        let shared = socket("socket", &self.socket)?;
+        let settings = BridgeSettings {
+            mode: self.mode.unwrap_or(defaults.mode),
+            retry,
+            connect: self.connect.unwrap_or(defaults.connect),
+            connect_order: self.connect_order.unwrap_or(defaults.connect_order),
+            buffer_size: self.buffer_size.unwrap_or(defaults.buffer_size),
+            max_buffer_size: self.max_buffer_size.or(defaults.max_buffer_size),
+            once: self.once.unwrap_or(defaults.once),
+            log_payload: self.log_payload.unwrap_or(defaults.log_payload),
+            payload_format: self.payload_format.unwrap_or(defaults.payload_format),
+            splice: self.splice.unwrap_or(defaults.splice),
+            pairing: self.pairing.unwrap_or(defaults.pairing),
+            max_pending: self.max_pending.unwrap_or(defaults.max_pending),
+            docker: defaults.docker.clone(),
+            capture: match self.capture {
+                Some(false) => None,
+                _ => defaults.capture.clone(),
+            },
+            record: self.record.clone().or_else(|| defaults.record.clone()),
+            idle_timeout: seconds("idle_timeout", self.idle_timeout)?.or(defaults.idle_timeout),
+            read_timeouts: [
+                seconds("container1_read_timeout", self.container1_read_timeout)?
+                    .or(read_timeout)
+                    .or(defaults.read_timeouts[0]),
+                seconds("container2_read_timeout", self.container2_read_timeout)?
+                    .or(read_timeout)
+                    .or(defaults.read_timeouts[1]),
+            ],
+            max_lifetime: seconds("max_lifetime", self.max_lifetime)?.or(defaults.max_lifetime),
+            sockets: [
+                socket("container1_socket", &self.container1_socket)?
+                    .or(&shared)
+                    .or(&defaults.sockets[0]),
+                socket("container2_socket", &self.container2_socket)?
+                    .or(&shared)
+                    .or(&defaults.sockets[1]),
+            ],
+        };
+        if settings
+            .max_buffer_size
+            .is_some_and(|max| max < settings.buffer_size)
+        {
+            return Err(invalid_data(format!(
+                "{}: max_buffer_size must be at least buffer_size",
+                self.name
+            )));
+        }
+        Ok(ContainerBridge::new(
+            &self.name,
+            self.container1.clone(),
+            self.container2.clone(),
+            settings,
+        ))
+    }
+}
+
+fn seconds(value: Option<f64>) -> Result<Option<Duration>, String> {
+    value
+        .map(|secs| {
+            Duration::try_from_secs_f64(secs)
+                .map_err(|e| format!("invalid duration {}: {}", secs, e))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::{env, process};
+
+    /// Loads `contents` from a temporary file ending in `name`.
+    fn load(name: &str, contents: &str) -> io::Result<Config> {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!("docker-tcp-config-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!(
+            "{}-{}",
+            FILES.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        fs::write(&path, contents).unwrap();
+        let config = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn bridge(extra: &str) -> String {
+        format!(
+            "[[bridges]]\n\
+             name = \"db\"\n\
+             container1 = \"127.0.0.1:1\"\n\
+             container2 = \"127.0.0.1:2\"\n\
+             {}",
+            extra
+        )
+    }
+
+    fn build(extra: &str, defaults: &BridgeSettings) -> io::Result<BridgeSettings> {
+        let config = load("bridge.toml", &bridge(extra))?;
+        let bridge = config.bridges[0].build(defaults)?;
+        Ok(bridge.settings().clone())
+    }
+
+    fn assert_invalid<T: std::fmt::Debug>(result: io::Result<T>, error: &str) {
+        assert!(
+            result.as_ref().is_err_and(
+                |e| e.kind() == io::ErrorKind::InvalidData && e.to_string().contains(error)
+            ),
+            "expected `{}`, got {:?}",
+            error,
+            result
+        );
+    }
+
+    #[test]
+    fn format_follows_the_extension() {
+        let yaml = "bridges:
+  - name: db
+    container1: 127.0.0.1:1
+    container2: docker://db:5432
+    mode: listen
+";
+        for name in ["bridges.yaml", "bridges.yml"] {
+            let config = load(name, yaml).unwrap();
+            assert_eq!(config.bridges[0].mode, Some(Mode::Listen));
+            assert_eq!(config.bridges[0].container2.to_string(), "docker://db:5432");
+        }
+        assert_eq!(
+            load("bridges.toml", &bridge("")).unwrap().bridges[0].name,
+            "db"
+        );
+        // Anything else is read as TOML.
+        assert!(load("bridges.conf", &bridge("")).is_ok());
+        assert_invalid(load("bridges.toml", yaml), "");
+        assert_invalid(load("bridges.yaml", &bridge("")), "");
+    }
+
+    #[test]
+    fn rejects_invalid_bridge_lists() {
+        assert_invalid(load("empty.toml", "bridges = []"), "no bridges configured");
+        let twice = format!("{}\n{}", bridge(""), bridge(""));
+        assert_invalid(load("twice.toml", &twice), "duplicate bridge name `db`");
+        let unnamed = bridge("").replace("name = \"db\"", "name = \"\"");
+        assert_invalid(load("unnamed.toml", &unnamed), "must not be empty");
+        assert_invalid(load("bad.toml", &bridge("max_pending = 0")), "max_pending");
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        for extra in [
+            "buffer = 1024",
+            "retry = { intervall = 1 }",
+            "socket = { no_delay = true }",
+            "container1_socket = { keepalive_time = 1 }",
+        ] {
+            assert_invalid(load("bridge.toml", &bridge(extra)), "unknown field");
+        }
+        assert_invalid(
+            load("top.toml", &format!("listen = 1\n{}", bridge(""))),
+            "unknown field",
+        );
+    }
+
+    #[test]
+    fn bridge_settings_override_the_defaults() {
+        let defaults = BridgeSettings {
+            retry: RetryPolicy {
+                interval: Duration::from_secs(2),
+                max_attempts: Some(3),
+                connect_timeouts: [Some(Duration::from_secs(1)); 2],
+                ..RetryPolicy::default()
+            },
+            read_timeouts: [Some(Duration::from_secs(9)); 2],
+            sockets: [0, 1].map(|_| SocketOptions {
+                nodelay: Some(true),
+                send_buffer_size: Some(4096),
+                ..SocketOptions::default()
+            }),
+            ..BridgeSettings::default()
+        };
+        let settings = build(
+            "retry.backoff = \"exponential\"\n\
+             retry.interval = 0.5\n\
+             retry.container2_connect_timeout = 4\n\
+             container1_read_timeout = 30\n\
+             socket = { keepalive = 60 }\n\
+             container2_socket = { nodelay = false, keepalive = 10 }\n",
+            &defaults,
+        )
+        .unwrap();
+
+        let retry = &settings.retry;
+        assert_eq!(retry.backoff, Backoff::Exponential);
+        assert_eq!(retry.interval, Duration::from_millis(500));
+        assert_eq!(retry.max_attempts, Some(3));
+        assert_eq!(
+            retry.connect_timeouts,
+            [Some(Duration::from_secs(1)), Some(Duration::from_secs(4))]
+        );
+        assert_eq!(
+            settings.read_timeouts,
+            [Some(Duration::from_secs(30)), Some(Duration::from_secs(9))]
+        );
+        let [socket1, socket2] = &settings.sockets;
+        assert_eq!(
+            *socket1,
+            SocketOptions {
+                nodelay: Some(true),
+                keepalive: Some(Duration::from_secs(60)),
+                send_buffer_size: Some(4096),
+                ..SocketOptions::default()
+            }
+        );
+        assert_eq!(
+            *socket2,
+            SocketOptions {
+                nodelay: Some(false),
+                keepalive: Some(Duration::from_secs(10)),
+                send_buffer_size: Some(4096),
+                ..SocketOptions::default()
+            }
+        );
+
+        let settings = build("", &defaults).unwrap();
+        assert_eq!(settings.retry.interval, Duration::from_secs(2));
+        assert_eq!(settings.sockets, defaults.sockets);
+        assert_invalid(
+            build("retry = { jitter = 2 }", &defaults),
+            "db: retry: jitter",
+        );
+        assert_invalid(build("idle_timeout = -1", &defaults), "db: idle_timeout");
+    }
+
+    #[test]
+    fn checks_buffer_sizes() {
+        let defaults = BridgeSettings::default();
+        assert_invalid(build("buffer_size = 0", &defaults), "db: buffer_size");
+        assert_invalid(
+            build("buffer_size = 8192\nmax_buffer_size = 4096", &defaults),
+            "db: max_buffer_size must be at least buffer_size",
+        );
+        // The check also covers sizes taken from the defaults.
+        assert_invalid(
+            build("max_buffer_size = 512", &defaults),
+            "max_buffer_size must be at least buffer_size",
+        );
+        let settings = build("buffer_size = 4096\nmax_buffer_size = 4096", &defaults).unwrap();
+        assert_eq!(
+            (settings.buffer_size, settings.max_buffer_size),
+            (4096, Some(4096))
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use crate::bridge::{BridgeSettings, ContainerBridge, Mode};
+use crate::docker::{ContainerSummary, DockerClient};
+use crate::endpoint::{DockerEndpoint, Endpoint};
+use crate::events::{ContainerAction, ContainerEvent, EventListener};
+use crate::supervisor::Supervisor;
+use log::{info, warn};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+const PEER_LABEL: &str = "tcp-bridge.peer";
+
+/// Creates, updates and removes bridges from the labels of running
+/// containers:
+///
+/// - `tcp-bridge.peer=CONTAINER:PORT` (required) the container to bridge to
+/// - `tcp-bridge.port=PORT` dial this port of the labelled container and
+///   splice it with the peer, or
+/// - `tcp-bridge.listen=ADDR` accept clients on `ADDR` and forward them to
+///   the peer
+/// - `tcp-bridge.network=NET` the network both containers are reached on
+/// - `tcp-bridge.name=NAME` the bridge name, defaulting to the container's
+pub struct Discovery {
+    docker: DockerClient,
+    supervisor: Arc<Supervisor>,
+    defaults: BridgeSettings,
+    interval: Duration,
+    rescan: Wakeup,
+}
+
+/// What earlier rescans found.
+#[derive(Default)]
+struct Known {
+    bridges: HashMap<String, BridgeSpec>,
+    /// Problems already warned about. Each is only warned about again once
+    /// it has gone away and come back, rather than on every rescan.
+    problems: HashSet<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BridgeSpec {
+    mode: Mode,
+    container1: Endpoint,
+    container2: Endpoint,
+}
+
+impl Discovery {
+    pub fn new(
+        supervisor: Arc<Supervisor>,
+        defaults: BridgeSettings,
+        interval: Duration,
+    ) -> Arc<Self> {
+        Arc::new(Discovery {
+            docker: defaults.docker.clone(),
+            supervisor,
+            defaults,
+            interval,
+            rescan: Wakeup::default(),
+        })
+    }
+
+    /// Rescans on every `interval`, or sooner when a container starts or dies.
+    pub fn run(&self) -> ! {
+        info!("Discovering bridges from `{}` container labels", PEER_LABEL);
+        let mut known = Known::default();
+        loop {
+            match self.docker.containers_with_label(PEER_LABEL) {
+                Ok(containers) => self.reconcile(&mut known, &containers),
+                Err(e) => warn!("Couldn't list containers for discovery: {}", e),
+            }
+            self.rescan.sleep(self.interval);
+        }
+    }
+
+    fn reconcile(&self, known: &mut Known, containers: &[ContainerSummary]) {
+        let mut problems = HashSet::new();
+        // Bridge names to the container they are discovered from.
+        let mut wanted: HashMap<String, (&str, BridgeSpec)> = HashMap::new();
+        for container in containers {
+            let Some(name) = container.name() else {
+                continue;
+            };
+            let (bridge, spec) = match bridge_spec(name, &container.labels) {
+                Ok(found) => found,
+                Err(e) => {
+                    problems.insert(format!("Ignoring labels of container {}: {}", name, e));
+                    continue;
+                }
+            };
+            // Of containers naming the same bridge, the one it is already
+            // running for wins, or else the first listed.
+            let ignored = match wanted.entry(bridge.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert((name, spec));
+                    continue;
+                }
+                Entry::Occupied(mut entry) if known.bridges.get(&bridge) == Some(&spec) => {
+                    entry.insert((name, spec)).0
+                }
+                Entry::Occupied(_) => name,
+            };
+            problems.insert(format!(
+                "Ignoring labels of container {}: another container already names bridge `{}`",
+                ignored, bridge
+            ));
+        }
+        let mut wanted: HashMap<String, BridgeSpec> = wanted
+            .into_iter()
+            .map(|(bridge, (_, spec))| (bridge, spec))
+            .collect();
+
+        known.bridges.retain(|name, spec| {
+            let keep = wanted.get(name) == Some(spec);
+            if !keep {
+                info!(bridge = name.as_str(); "Discovered bridge changed or went away");
+                self.supervisor.remove(name);
+            }
+            keep
+        });
+
+        wanted.retain(|name, _| !known.bridges.contains_key(name));
+        for (name, spec) in wanted {
+            info!(
+                bridge = name.as_str();
+                "Discovered bridge {} <-> {}",
+                spec.container1, spec.container2
+            );
+            let settings = BridgeSettings {
+                mode: spec.mode,
+                ..self.defaults.clone()
+            };
+            let bridge = ContainerBridge::new(
+                &name,
+                spec.container1.clone(),
+                spec.container2.clone(),
+                settings,
+            );
+            match self.supervisor.add(bridge) {
+                Ok(()) => {
+                    known.bridges.insert(name, spec);
+                }
+                // Most likely a bridge from the config file, which stays.
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    problems.insert(format!("Not starting discovered bridge: {}", e));
+                }
+                Err(e) => warn!(bridge = name.as_str(); "Couldn't start discovered bridge: {}", e),
+            }
+        }
+
+        for problem in problems.difference(&known.problems) {
+            warn!("{}", problem);
+        }
+        known.problems = problems;
+    }
+}
+
+/// Interruptible sleep between rescans.
+#[derive(Default)]
+struct Wakeup {
+    woken: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Wakeup {
+    fn notify(&self) {
+        *self.woken.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+
+    /// Sleeps for `timeout` or until notified. Returns whether it was notified.
+    fn sleep(&self, timeout: Duration) -> bool {
+        let woken = self.woken.lock().unwrap();
+        let (mut woken, _) = self
+            .condvar
+            .wait_timeout_while(woken, timeout, |woken| !*woken)
+            .unwrap();
+        std::mem::take(&mut *woken)
+    }
+}
+
+impl EventListener for Discovery {
+    fn container_event(&self, event: &ContainerEvent) {
+        if let ContainerAction::Start | ContainerAction::Die = event.action {
+            self.rescan.notify();
+        }
+    }
+}
+
+fn bridge_spec(
+    container: &str,
+    labels: &HashMap<String, String>,
+) -> Result<(String, BridgeSpec), String> {
+    let label = |name: &str| labels.get(&format!("tcp-bridge.{}", name));
+    let network = label("network").cloned();
+
+    let peer = label("peer").ok_or("missing tcp-bridge.peer")?;
+    let peer = match peer.parse()? {
+        Endpoint::Host(target) => Endpoint::Docker(docker_endpoint(&target, network.clone())?),
+        endpoint => endpoint,
+    };
+
+    let (mode, container1) = match (label("port"), label("listen")) {
+        (Some(port), None) => {
+            let endpoint = docker_endpoint(&format!("{}:{}", container, port), network)?;
+            (Mode::Dial, Endpoint::Docker(endpoint))
+        }
+        (None, Some(listen)) => (Mode::Listen, listen.parse()?),
+        _ => return Err("exactly one of tcp-bridge.port and tcp-bridge.listen is needed".into()),
+    };
+
+    let name = label("name").map_or(container, String::as_str).to_string();
+    Ok((
+        name,
+        BridgeSpec {
+            mode,
+            container1,
+            container2: peer,
+        },
+    ))
+}
+
+fn docker_endpoint(target: &str, network: Option<String>) -> Result<DockerEndpoint, String> {
+    match format!("docker://{}", target).parse()? {
+        Endpoint::Docker(endpoint) => Ok(DockerEndpoint {
+            network: network.or(endpoint.network),
+            ..endpoint
+        }),
+        Endpoint::Host(_) => unreachable!("docker:// endpoints always parse as Docker"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(name: &str, labels: &[(&str, &str)]) -> ContainerSummary {
+        ContainerSummary {
+            names: vec![format!("/{}", name)],
+            labels: labels
+                .iter()
+                .map(|(label, value)| (format!("tcp-bridge.{}", label), value.to_string()))
+                .collect(),
+        }
+    }
+
+    fn listening(name: &str, bridge: &str, peer: &str) -> ContainerSummary {
+        container(
+            name,
+            &[("name", bridge), ("listen", "127.0.0.1:0"), ("peer", peer)],
+        )
+    }
+
+    fn peer(known: &Known, bridge: &str) -> String {
+        known.bridges[bridge].container2.to_string()
+    }
+
+    #[test]
+    fn reads_bridges_from_labels() {
+        let labels = container(
+            "web",
+            &[("port", "80"), ("peer", "db:5432"), ("network", "back")],
+        );
+        let (name, spec) = bridge_spec("web", &labels.labels).unwrap();
+        assert_eq!(name, "web");
+        assert_eq!(spec.mode, Mode::Dial);
+        assert_eq!(spec.container1.to_string(), "docker://web:80?network=back");
+        assert_eq!(spec.container2.to_string(), "docker://db:5432?network=back");
+
+        for labels in [
+            container("web", &[("port", "80")]),
+            container("web", &[("peer", "db:5432")]),
+            container(
+                "web",
+                &[("port", "80"), ("listen", ":80"), ("peer", "db:5432")],
+            ),
+        ] {
+            assert!(bridge_spec("web", &labels.labels).is_err());
+        }
+    }
+
+    #[test]
+    fn keeps_the_first_container_naming_a_bridge() {
+        let supervisor = Supervisor::new(None);
+        let discovery = Discovery::new(
+            supervisor.clone(),
+            BridgeSettings::default(),
+            Duration::from_secs(60),
+        );
+        let mut known = Known::default();
+
+        let a = || listening("a", "shared", "db:5432");
+        let b = || listening("b", "shared", "cache:6379");
+        discovery.reconcile(&mut known, &[a(), b()]);
+        assert_eq!(peer(&known, "shared"), "docker://db:5432");
+        assert_eq!(known.problems.len(), 1);
+
+        // The running bridge is kept even once the other container is
+        // listed first.
+        discovery.reconcile(&mut known, &[b(), a()]);
+        assert_eq!(peer(&known, "shared"), "docker://db:5432");
+
+        discovery.reconcile(&mut known, &[b()]);
+        assert_eq!(peer(&known, "shared"), "docker://cache:6379");
+        assert!(known.problems.is_empty());
+        supervisor.stop();
+    }
+
+    #[test]
+    fn leaves_bridges_of_the_same_name_alone() {
+        let supervisor = Supervisor::new(None);
+        let configured = ContainerBridge::new(
+            "web",
+            "127.0.0.1:0".parse().unwrap(),
+            "docker://db:5432".parse().unwrap(),
+            BridgeSettings {
+                mode: Mode::Listen,
+                ..BridgeSettings::default()
+            },
+        );
+        supervisor.add(configured).unwrap();
+        let discovery = Discovery::new(
+            supervisor.clone(),
+            BridgeSettings::default(),
+            Duration::from_secs(60),
+        );
+        let mut known = Known::default();
+
+        let labelled = [listening("web", "web", "cache:6379")];
+        for _ in 0..2 {
+            discovery.reconcile(&mut known, &labelled);
+            assert!(known.bridges.is_empty());
+            assert_eq!(known.problems.len(), 1);
+        }
+        assert!(supervisor.get("web").is_some());
+        supervisor.stop();
+    }
+}
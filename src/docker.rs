@@ -0,0 +1,614 @@
+use crate::invalid_data;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// Minimal client for the Docker Engine API over its Unix socket.
+#[derive(Debug, Clone)]
+pub struct DockerClient {
+    socket: PathBuf,
+}
+
+impl Default for DockerClient {
+    /// Uses `DOCKER_HOST` when it names a Unix socket, otherwise the
+    /// standard `/var/run/docker.sock`.
+    fn default() -> Self {
+        let socket = env::var("DOCKER_HOST")
+            .ok()
+            .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET));
+        DockerClient { socket }
+    }
+}
+
+impl DockerClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        DockerClient {
+            socket: socket.into(),
+        }
+    }
+
+    /// Sends a GET request and returns the status code and a reader over
+    /// the (de-chunked) response body.
+    pub fn get(&self, path: &str, timeout: Option<Duration>) -> io::Result<Response> {
+        let mut stream = UnixStream::connect(&self.socket).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("docker socket {}: {}", self.socket.display(), e),
+            )
+        })?;
+        stream.set_read_timeout(timeout)?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\n\r\n",
+            path
+        )?;
+        Response::read(BufReader::new(stream))
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, path: &str) -> io::Result<T> {
+        let response = self.get(path, Some(Duration::from_secs(10)))?;
+        let status = response.status;
+        let body = response.into_bytes()?;
+        if !(200..300).contains(&status) {
+            let message = serde_json::from_slice::<ErrorMessage>(&body)
+                .map(|error| error.message)
+                .unwrap_or_else(|_| String::from_utf8_lossy(&body).into_owned());
+            let kind = if status == 404 {
+                io::ErrorKind::NotFound
+            } else {
+                io::ErrorKind::Other
+            };
+            return Err(io::Error::new(
+                kind,
+                format!("docker API {}: {}", status, message.trim()),
+            ));
+        }
+        serde_json::from_slice(&body).map_err(invalid_data)
+    }
+
+    pub fn inspect(&self, container: &str) -> io::Result<ContainerInspect> {
+        self.get_json(&format!("/containers/{}/json", container))
+    }
+
+    /// Running containers that carry the given label.
+    pub fn containers_with_label(&self, label: &str) -> io::Result<Vec<ContainerSummary>> {
+        let filters = serde_json::json!({ "label": [label] }).to_string();
+        self.get_json(&format!(
+            "/containers/json?filters={}",
+            encode_query(&filters)
+        ))
+    }
+
+    /// Subscribes to the `/events` stream, restricted by the given JSON
+    /// `filters`. The iterator blocks until the next event arrives.
+    pub fn events(&self, filters: &str) -> io::Result<impl Iterator<Item = io::Result<Event>>> {
+        let response = self.get(&format!("/events?filters={}", encode_query(filters)), None)?;
+        if response.status != 200 {
+            let status = response.status;
+            let body = response.into_bytes()?;
+            return Err(io::Error::other(format!(
+                "docker API {}: {}",
+                status,
+                String::from_utf8_lossy(&body).trim()
+            )));
+        }
+        Ok(serde_json::Deserializer::from_reader(response)
+            .into_iter::<Event>()
+            .map(|event| event.map_err(io::Error::from)))
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    body: Box<dyn Read + Send>,
+}
+
+impl Response {
+    fn read(mut reader: BufReader<UnixStream>) -> io::Result<Response> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| invalid_data(format!("bad status line {:?}", line)))?;
+
+        let mut chunked = false;
+        let mut content_length = None;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("headers ended early"));
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                let value = value.trim();
+                if name.eq_ignore_ascii_case("transfer-encoding") {
+                    chunked = value.eq_ignore_ascii_case("chunked");
+                } else if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.parse::<u64>().ok();
+                }
+            }
+        }
+
+        let body: Box<dyn Read + Send> = match (chunked, content_length) {
+            (true, _) => Box::new(ChunkedReader::new(reader)),
+            (false, Some(length)) => Box::new(reader.take(length)),
+            (false, None) => Box::new(reader),
+        };
+        Ok(Response { status, body })
+    }
+
+    pub fn into_bytes(mut self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self.body.read_to_end(&mut body)?;
+        Ok(body)
+    }
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body. The CRLF ending a chunk is
+/// consumed lazily so streamed bodies are handed out as soon as they arrive.
+struct ChunkedReader<R> {
+    inner: R,
+    remaining: usize,
+    after_chunk: bool,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(inner: R) -> Self {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            after_chunk: false,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            if self.after_chunk {
+                let mut crlf = [0; 2];
+                self.inner.read_exact(&mut crlf)?;
+            }
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+            let size = line.trim().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| invalid_data(format!("bad chunk size {:?}", line)))?;
+            if size == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+            self.remaining = size;
+            self.after_chunk = true;
+        }
+
+        let limit = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..limit])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerSummary {
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub labels: HashMap<String, String>,
+}
+
+impl ContainerSummary {
+    pub fn name(&self) -> Option<&str> {
+        self.names.first().map(|name| name.trim_start_matches('/'))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    #[serde(rename = "Type", default)]
+    pub kind: String,
+    #[serde(rename = "Action", default)]
+    pub action: String,
+    #[serde(rename = "Actor")]
+    pub actor: Actor,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Actor {
+    #[serde(rename = "Attributes", default)]
+    pub attributes: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorMessage {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerInspect {
+    pub name: String,
+    pub network_settings: NetworkSettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetworkSettings {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub networks: HashMap<String, Network>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub ports: HashMap<String, Option<Vec<PortBinding>>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Network {
+    #[serde(rename = "IPAddress", default)]
+    pub ip_address: String,
+    #[serde(rename = "GlobalIPv6Address", default)]
+    pub global_ipv6_address: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PortBinding {
+    #[serde(default)]
+    pub host_ip: String,
+    pub host_port: String,
+}
+
+impl ContainerInspect {
+    /// Address of `port` on the container's IP in `network`, or in the
+    /// first network (by name) that has an address when none is given.
+    pub fn network_addr(&self, network: Option<&str>, port: u16) -> io::Result<SocketAddr> {
+        let mut networks: Vec<_> = self.network_settings.networks.iter().collect();
+        networks.sort_by(|a, b| a.0.cmp(b.0));
+        let ip = networks
+            .into_iter()
+            .filter(|(name, _)| network.is_none_or(|wanted| wanted == name.as_str()))
+            .find_map(|(_, network)| network.ip())
+            .ok_or_else(|| {
+                let reason = match network {
+                    Some(network) => format!("has no address on network `{}`", network),
+                    None => "has no network address (is it running?)".to_string(),
+                };
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("container {} {}", self.display_name(), reason),
+                )
+            })?;
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    /// Host address the container's `port/tcp` is published on.
+    pub fn published_addr(&self, port: u16) -> io::Result<SocketAddr> {
+        self.network_settings
+            .ports
+            .get(&format!("{}/tcp", port))
+            .and_then(|bindings| bindings.as_ref())
+            .and_then(|bindings| bindings.iter().find_map(PortBinding::addr))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "container {} does not publish port {}/tcp",
+                        self.display_name(),
+                        port
+                    ),
+                )
+            })
+    }
+
+    fn display_name(&self) -> &str {
+        self.name.trim_start_matches('/')
+    }
+}
+
+impl Network {
+    fn ip(&self) -> Option<IpAddr> {
+        [&self.ip_address, &self.global_ipv6_address]
+            .into_iter()
+            .find_map(|ip| ip.parse().ok())
+    }
+}
+
+impl PortBinding {
+    /// Wildcard bindings are reached through loopback.
+    fn addr(&self) -> Option<SocketAddr> {
+        let port = self.host_port.parse().ok()?;
+        let ip = match self.host_ip.as_str() {
+            "" | "0.0.0.0" => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            "::" => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip.parse().ok()?,
+        };
+        Some(SocketAddr::new(ip, port))
+    }
+}
+
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The API reports some empty collections as `null` rather than omitting them.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+/// A stand-in for the Docker API on a Unix socket, for tests.
+#[cfg(test)]
+pub mod fake {
+    use super::DockerClient;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::{env, fs, process, thread};
+
+    pub struct FakeDocker {
+        pub client: DockerClient,
+        socket: PathBuf,
+    }
+
+    impl FakeDocker {
+        /// Serves each request on its own thread, passing the request path
+        /// and the connection to `handler` to answer.
+        pub fn serve<F>(handler: F) -> Self
+        where
+            F: Fn(&str, UnixStream) + Send + Sync + 'static,
+        {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let socket = env::temp_dir().join(format!(
+                "docker-tcp-test-{}-{}.sock",
+                process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = fs::remove_file(&socket);
+            let listener = UnixListener::bind(&socket).unwrap();
+            let handler = Arc::new(handler);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        return;
+                    };
+                    let handler = Arc::clone(&handler);
+                    thread::spawn(move || {
+                        let mut reader = BufReader::new(&stream);
+                        let mut request = String::new();
+                        reader.read_line(&mut request).unwrap();
+                        let mut header = String::new();
+                        while reader.read_line(&mut header).unwrap() > 2 {
+                            header.clear();
+                        }
+                        let path = request.split_whitespace().nth(1).unwrap_or_default();
+                        handler(path, stream.try_clone().unwrap());
+                    });
+                }
+            });
+            FakeDocker {
+                client: DockerClient::new(&socket),
+                socket,
+            }
+        }
+
+        /// Answers `/containers/NAME/json` with the bodies given per name,
+        /// and 404 for other containers.
+        pub fn containers(containers: &[(&str, &str)], chunked: bool) -> Self {
+            let containers = inspections(containers);
+            FakeDocker::serve(move |path, mut stream| {
+                inspect(&containers, path, &mut stream, chunked)
+            })
+        }
+
+        /// Like `containers`, and streams each line sent on the returned
+        /// channel as an event to the first `/events` subscriber.
+        pub fn with_events(containers: &[(&str, &str)]) -> (Self, mpsc::Sender<String>) {
+            let containers = inspections(containers);
+            let (sender, receiver) = mpsc::channel::<String>();
+            let receiver = Mutex::new(receiver);
+            let docker = FakeDocker::serve(move |path, mut stream| {
+                if !path.starts_with("/events?") {
+                    return inspect(&containers, path, &mut stream, true);
+                }
+                let receiver = receiver.lock().unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n")
+                    .unwrap();
+                for line in receiver.iter() {
+                    let line = line + "\n";
+                    write!(stream, "{:x}\r\n{}\r\n", line.len(), line).unwrap();
+                }
+            });
+            (docker, sender)
+        }
+    }
+
+    impl Drop for FakeDocker {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.socket);
+        }
+    }
+
+    /// A container event as the API streams it.
+    pub fn event(action: &str, container: &str) -> String {
+        serde_json::json!({
+            "Type": "container",
+            "Action": action,
+            "Actor": {"ID": "0123abcd", "Attributes": {"name": container, "image": "redis"}},
+        })
+        .to_string()
+    }
+
+    fn inspections(containers: &[(&str, &str)]) -> Vec<(String, String)> {
+        containers
+            .iter()
+            .map(|(name, body)| (format!("/containers/{}/json", name), body.to_string()))
+            .collect()
+    }
+
+    fn inspect(
+        containers: &[(String, String)],
+        path: &str,
+        stream: &mut UnixStream,
+        chunked: bool,
+    ) {
+        match containers.iter().find(|(known, _)| known == path) {
+            Some((_, body)) => reply(stream, 200, body, chunked),
+            None => reply(stream, 404, r#"{"message": "No such container"}"#, chunked),
+        }
+    }
+
+    /// Writes a whole response, with the body in a few chunks or with its
+    /// length up front.
+    pub fn reply(stream: &mut UnixStream, status: u16, body: &str, chunked: bool) {
+        write!(stream, "HTTP/1.1 {} Fake\r\n", status).unwrap();
+        if chunked {
+            stream
+                .write_all(b"Transfer-Encoding: chunked\r\n\r\n")
+                .unwrap();
+            for chunk in body.as_bytes().chunks(7) {
+                write!(stream, "{:x};ext=1\r\n", chunk.len()).unwrap();
+                stream.write_all(chunk).unwrap();
+                stream.write_all(b"\r\n").unwrap();
+            }
+            stream.write_all(b"0\r\n\r\n").unwrap();
+        } else {
+            write!(stream, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::FakeDocker;
+    use super::*;
+
+    const WEB: &str = r#"{
+        "Name": "/web",
+        "NetworkSettings": {
+            "Networks": {
+                "bridge": {"IPAddress": "172.17.0.2"},
+                "app": {"IPAddress": "10.0.0.5"},
+                "v6only": {"IPAddress": "", "GlobalIPv6Address": "fd00::5"},
+                "down": {"IPAddress": ""}
+            },
+            "Ports": {
+                "80/tcp": [{"HostIp": "0.0.0.0", "HostPort": "8080"}],
+                "443/tcp": [{"HostIp": "::", "HostPort": "8443"}],
+                "5432/tcp": [{"HostIp": "192.168.1.2", "HostPort": "15432"}],
+                "53/tcp": null
+            }
+        }
+    }"#;
+
+    fn inspect(chunked: bool) -> ContainerInspect {
+        let docker = FakeDocker::containers(&[("web", WEB)], chunked);
+        docker.client.inspect("web").unwrap()
+    }
+
+    #[test]
+    fn inspect_reads_content_length_and_chunked_bodies() {
+        for chunked in [false, true] {
+            let container = inspect(chunked);
+            assert_eq!(container.name, "/web");
+            assert_eq!(container.network_settings.networks.len(), 4);
+        }
+    }
+
+    #[test]
+    fn network_addr_picks_the_network() {
+        let container = inspect(false);
+        let addr = |network| {
+            container
+                .network_addr(network, 6379)
+                .map(|addr| addr.to_string())
+        };
+        // The first network by name with an address.
+        assert_eq!(addr(None).unwrap(), "10.0.0.5:6379");
+        assert_eq!(addr(Some("bridge")).unwrap(), "172.17.0.2:6379");
+        assert_eq!(addr(Some("v6only")).unwrap(), "[fd00::5]:6379");
+        for network in ["down", "missing"] {
+            let error = addr(Some(network)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            assert!(error.to_string().contains(network), "{}", error);
+        }
+    }
+
+    #[test]
+    fn published_addr_maps_wildcards_to_loopback() {
+        let container = inspect(true);
+        let addr = |port| container.published_addr(port).map(|addr| addr.to_string());
+        assert_eq!(addr(80).unwrap(), "127.0.0.1:8080");
+        assert_eq!(addr(443).unwrap(), "[::1]:8443");
+        assert_eq!(addr(5432).unwrap(), "192.168.1.2:15432");
+        for port in [53, 22] {
+            assert_eq!(addr(port).unwrap_err().kind(), io::ErrorKind::NotFound);
+        }
+    }
+
+    #[test]
+    fn missing_container_is_not_found() {
+        for chunked in [false, true] {
+            let docker = FakeDocker::containers(&[("web", WEB)], chunked);
+            let error = docker.client.inspect("db").unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            assert_eq!(error.to_string(), "docker API 404: No such container");
+        }
+    }
+
+    #[test]
+    fn chunked_reader_stops_at_the_last_chunk() {
+        let body = "4\r\nWiki\r\n5;name=value\r\npedia\r\n0\r\n\r\ntrailing";
+        let mut reader = ChunkedReader::new(body.as_bytes());
+        let mut decoded = String::new();
+        reader.read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "Wikipedia");
+    }
+
+    #[test]
+    fn chunked_reader_rejects_bad_sizes() {
+        let mut reader = ChunkedReader::new("zz\r\nWiki\r\n".as_bytes());
+        let error = reader.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
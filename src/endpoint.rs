@@ -0,0 +1,259 @@
+use crate::docker::DockerClient;
+use log::debug;
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+/// Where one side of a bridge lives.
+///
+/// Either a plain `host:port`, or a Docker container given as
+/// `docker://NAME:PORT`, optionally followed by `?network=NET` to pick the
+/// network whose IP is used, or `?via=published` to use the host port the
+/// container port is published on instead.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Endpoint {
+    Host(String),
+    Docker(DockerEndpoint),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DockerEndpoint {
+    pub container: String,
+    pub port: u16,
+    pub network: Option<String>,
+    pub published: bool,
+}
+
+impl Endpoint {
+    /// Looks the endpoint up afresh, so changed container IPs are picked up
+    /// on every call.
+    pub fn resolve(&self, docker: &DockerClient) -> io::Result<SocketAddr> {
+        let addr = match self {
+            Endpoint::Host(host) => host.to_socket_addrs()?.next().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} did not resolve to any address", host),
+                )
+            })?,
+            Endpoint::Docker(target) => {
+                let container = docker.inspect(&target.container)?;
+                if target.published {
+                    container.published_addr(target.port)?
+                } else {
+                    container.network_addr(target.network.as_deref(), target.port)?
+                }
+            }
+        };
+        if let Endpoint::Docker(_) = self {
+            debug!("{} resolved to {}", self, addr);
+        }
+        Ok(addr)
+    }
+
+    /// Name of the container behind the endpoint, if it is a Docker one.
+    pub fn container(&self) -> Option<&str> {
+        match self {
+            Endpoint::Host(_) => None,
+            Endpoint::Docker(target) => Some(&target.container),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("docker://") {
+            Some(target) => parse_docker(target).map(Endpoint::Docker),
+            None => {
+                if s.parse::<SocketAddr>().is_ok() {
+                    return Ok(Endpoint::Host(s.to_string()));
+                }
+                match s.rsplit_once(':') {
+                    Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                        Ok(Endpoint::Host(s.to_string()))
+                    }
+                    _ => Err(format!(
+                        "`{}` is not a valid endpoint (expected HOST:PORT or docker://CONTAINER:PORT)",
+                        s
+                    )),
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Endpoint {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+fn parse_docker(target: &str) -> Result<DockerEndpoint, String> {
+    let (target, query) = target.split_once('?').unwrap_or((target, ""));
+    let (container, port) = target.rsplit_once(':').ok_or_else(|| {
+        format!(
+            "docker://{} needs a port, e.g. docker://my-redis:6379",
+            target
+        )
+    })?;
+    let valid_name = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-');
+    if container.is_empty() || !container.chars().all(valid_name) {
+        return Err(format!("`{}` is not a valid container name", container));
+    }
+    let port = port
+        .parse()
+        .map_err(|_| format!("`{}` is not a valid port", port))?;
+
+    let mut endpoint = DockerEndpoint {
+        container: container.to_string(),
+        port,
+        network: None,
+        published: false,
+    };
+    for option in query.split('&').filter(|option| !option.is_empty()) {
+        match option.split_once('=') {
+            Some(("network", network)) if !network.is_empty() => {
+                endpoint.network = Some(network.to_string())
+            }
+            Some(("via", "network")) => endpoint.published = false,
+            Some(("via", "published")) => endpoint.published = true,
+            _ => return Err(format!("unknown docker endpoint option `{}`", option)),
+        }
+    }
+    if endpoint.published && endpoint.network.is_some() {
+        return Err("`network` and `via=published` can't be combined".to_string());
+    }
+    Ok(endpoint)
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Host(host) => f.write_str(host),
+            Endpoint::Docker(target) => {
+                write!(f, "docker://{}:{}", target.container, target.port)?;
+                if let Some(network) = &target.network {
+                    write!(f, "?network={}", network)?;
+                } else if target.published {
+                    f.write_str("?via=published")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::fake::FakeDocker;
+
+    fn docker(target: &str) -> DockerEndpoint {
+        match target.parse() {
+            Ok(Endpoint::Docker(endpoint)) => endpoint,
+            other => panic!("{} parsed as {:?}", target, other),
+        }
+    }
+
+    #[test]
+    fn parses_docker_endpoints() {
+        assert_eq!(
+            docker("docker://my-redis.1:6379"),
+            DockerEndpoint {
+                container: "my-redis.1".to_string(),
+                port: 6379,
+                network: None,
+                published: false,
+            }
+        );
+        assert_eq!(
+            docker("docker://db:5432?network=backend")
+                .network
+                .as_deref(),
+            Some("backend")
+        );
+        assert!(docker("docker://db:5432?via=published").published);
+        assert!(!docker("docker://db:5432?via=network").published);
+    }
+
+    #[test]
+    fn rejects_malformed_docker_endpoints() {
+        for (target, error) in [
+            ("docker://db", "needs a port"),
+            ("docker://:5432", "not a valid container name"),
+            ("docker://my/db:5432", "not a valid container name"),
+            ("docker://db:http", "not a valid port"),
+            ("docker://db:70000", "not a valid port"),
+            (
+                "docker://db:5432?network=",
+                "unknown docker endpoint option",
+            ),
+            (
+                "docker://db:5432?via=tunnel",
+                "unknown docker endpoint option",
+            ),
+            (
+                "docker://db:5432?user=root",
+                "unknown docker endpoint option",
+            ),
+            (
+                "docker://db:5432?network=a&via=published",
+                "can't be combined",
+            ),
+        ] {
+            let result = target.parse::<Endpoint>();
+            assert!(
+                result.as_ref().is_err_and(|e| e.contains(error)),
+                "{}: {:?}",
+                target,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        for target in [
+            "127.0.0.1:80",
+            "docker://db:5432",
+            "docker://db:5432?network=backend",
+            "docker://db:5432?via=published",
+        ] {
+            assert_eq!(target.parse::<Endpoint>().unwrap().to_string(), target);
+        }
+    }
+
+    #[test]
+    fn resolves_through_the_docker_api() {
+        let container = r#"{
+            "Name": "/db",
+            "NetworkSettings": {
+                "Networks": {"backend": {"IPAddress": "10.1.0.7"}},
+                "Ports": {"5432/tcp": [{"HostIp": "", "HostPort": "15432"}]}
+            }
+        }"#;
+        let docker = FakeDocker::containers(&[("db", container)], true);
+        let resolve = |target: &str| {
+            let endpoint: Endpoint = target.parse().unwrap();
+            endpoint.resolve(&docker.client)
+        };
+        assert_eq!(
+            resolve("docker://db:5432").unwrap().to_string(),
+            "10.1.0.7:5432"
+        );
+        assert_eq!(
+            resolve("docker://db:5432?via=published")
+                .unwrap()
+                .to_string(),
+            "127.0.0.1:15432"
+        );
+        let error = resolve("docker://cache:6379").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::docker::{DockerClient, Event};
+use log::{debug, info, warn};
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+const FILTERS: &str = r#"{"type":["container"],"event":["start","restart","die","health_status"]}"#;
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerAction {
+    Start,
+    Restart,
+    Die,
+    Healthy,
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEvent {
+    pub container: String,
+    pub action: ContainerAction,
+}
+
+impl ContainerEvent {
+    fn from_docker(event: &Event) -> Option<Self> {
+        if event.kind != "container" {
+            return None;
+        }
+        let action = match event.action.as_str() {
+            "start" => ContainerAction::Start,
+            "restart" => ContainerAction::Restart,
+            "die" => ContainerAction::Die,
+            "health_status: healthy" => ContainerAction::Healthy,
+            "health_status: unhealthy" => ContainerAction::Unhealthy,
+            _ => return None,
+        };
+        let container = event.actor.attributes.get("name")?.clone();
+        Some(ContainerEvent { container, action })
+    }
+}
+
+pub trait EventListener: Send + Sync {
+    fn container_event(&self, event: &ContainerEvent);
+}
+
+/// Follows the Docker `/events` stream on a background thread and hands
+/// container lifecycle events to every live listener.
+pub struct DockerEvents {
+    listeners: Mutex<Vec<Weak<dyn EventListener>>>,
+}
+
+impl DockerEvents {
+    pub fn spawn(docker: DockerClient) -> io::Result<Arc<Self>> {
+        let events = Arc::new(DockerEvents {
+            listeners: Mutex::new(Vec::new()),
+        });
+        let watcher = Arc::downgrade(&events);
+        thread::Builder::new()
+            .name("docker-events".to_string())
+            .spawn(move || follow(&docker, &watcher))?;
+        Ok(events)
+    }
+
+    /// Listeners are held weakly and forgotten once dropped.
+    pub fn subscribe(&self, listener: Weak<dyn EventListener>) {
+        self.listeners.lock().unwrap().push(listener);
+    }
+
+    fn dispatch(&self, event: &ContainerEvent) {
+        let listeners: Vec<_> = {
+            let mut listeners = self.listeners.lock().unwrap();
+            listeners.retain(|listener| listener.strong_count() > 0);
+            listeners.iter().filter_map(Weak::upgrade).collect()
+        };
+        for listener in listeners {
+            listener.container_event(event);
+        }
+    }
+}
+
+fn follow(docker: &DockerClient, events: &Weak<DockerEvents>) {
+    loop {
+        match docker.events(FILTERS) {
+            Ok(stream) => {
+                info!("Following Docker container events");
+                for event in stream {
+                    let Some(events) = events.upgrade() else {
+                        return;
+                    };
+                    match event {
+                        Ok(event) => {
+                            if let Some(event) = ContainerEvent::from_docker(&event) {
+                                debug!("Container {} event: {:?}", event.container, event.action);
+                                events.dispatch(&event);
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Docker event stream failed: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!("Couldn't subscribe to Docker events: {}", e),
+        }
+        if events.strong_count() == 0 {
+            return;
+        }
+        warn!(
+            "Resubscribing to Docker events in {:?}...",
+            RESUBSCRIBE_INTERVAL
+        );
+        thread::sleep(RESUBSCRIBE_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::fake::{event, FakeDocker};
+    use std::sync::mpsc;
+
+    fn parse(json: &str) -> Option<ContainerEvent> {
+        ContainerEvent::from_docker(&serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn maps_lifecycle_actions() {
+        for (action, expected) in [
+            ("start", ContainerAction::Start),
+            ("restart", ContainerAction::Restart),
+            ("die", ContainerAction::Die),
+            ("health_status: healthy", ContainerAction::Healthy),
+            ("health_status: unhealthy", ContainerAction::Unhealthy),
+        ] {
+            assert_eq!(
+                parse(&event(action, "db")),
+                Some(ContainerEvent {
+                    container: "db".to_string(),
+                    action: expected,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_other_events() {
+        assert_eq!(parse(&event("exec_start: sh", "db")), None);
+        assert_eq!(
+            parse(
+                r#"{"Type": "network", "Action": "die", "Actor": {"Attributes": {"name": "db"}}}"#
+            ),
+            None
+        );
+        assert_eq!(
+            parse(r#"{"Type": "container", "Action": "die", "Actor": {"Attributes": {}}}"#),
+            None
+        );
+    }
+
+    struct Recorder(Mutex<mpsc::Sender<ContainerEvent>>);
+
+    impl EventListener for Recorder {
+        fn container_event(&self, event: &ContainerEvent) {
+            self.0.lock().unwrap().send(event.clone()).unwrap();
+        }
+    }
+
+    #[test]
+    fn follows_the_event_stream() {
+        let (docker, stream) = FakeDocker::with_events(&[]);
+        let events = DockerEvents::spawn(docker.client.clone()).unwrap();
+        let (sender, received) = mpsc::channel();
+        let recorder: Arc<dyn EventListener> = Arc::new(Recorder(Mutex::new(sender)));
+        events.subscribe(Arc::downgrade(&recorder));
+
+        stream.send(event("start", "db")).unwrap();
+        stream.send(event("exec_start: sh", "db")).unwrap();
+        stream.send(event("die", "cache")).unwrap();
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            received.recv_timeout(timeout).unwrap(),
+            ContainerEvent {
+                container: "db".to_string(),
+                action: ContainerAction::Start,
+            }
+        );
+        assert_eq!(
+            received.recv_timeout(timeout).unwrap(),
+            ContainerEvent {
+                container: "cache".to_string(),
+                action: ContainerAction::Die,
+            }
+        );
+    }
+}
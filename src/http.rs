@@ -0,0 +1,187 @@
+use crate::invalid_data;
+use log::debug;
+use serde::Serialize;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::task;
+use tokio::time;
+
+/// Most a request's head may take up.
+const MAX_HEAD: usize = 16 * 1024;
+/// Most a request's body may take up.
+const MAX_BODY: usize = 1024 * 1024;
+/// How long a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Just enough of an HTTP/1.1 request for the built-in endpoints.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        let mut body = serde_json::to_string_pretty(value).unwrap_or_default();
+        body.push('\n');
+        Response {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", message),
+        }
+    }
+}
+
+/// Answers each connection on `listener` with one response from `handler`,
+/// then closes it. The handler may block, e.g. waiting for a bridge to stop.
+pub async fn serve<F>(listener: Listener, handler: F)
+where
+    F: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let accepted = match &listener {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .await
+                .map(|(stream, _)| spawn(stream, &handler)),
+            Listener::Unix(listener) => listener
+                .accept()
+                .await
+                .map(|(stream, _)| spawn(stream, &handler)),
+        };
+        if let Err(e) = accepted {
+            debug!("Couldn't accept an HTTP connection: {}", e);
+        }
+    }
+}
+
+fn spawn<S, F>(stream: S, handler: &Arc<F>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    let handler = handler.clone();
+    tokio::spawn(async move {
+        if let Err(e) = handle(stream, &*handler).await {
+            debug!("HTTP connection failed: {}", e);
+        }
+    });
+}
+
+async fn handle<S, F>(mut stream: S, handler: &F) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(Request) -> Response,
+{
+    let response = match time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => task::block_in_place(|| handler(request)),
+        Ok(Err(e)) if e.kind() == io::ErrorKind::InvalidData => Response::error(400, "Bad request"),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => Response::error(408, "Request timeout"),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn read_request<S>(stream: &mut S) -> io::Result<Request>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD {
+            return Err(invalid_data("request head too large"));
+        }
+        let mut byte = [0];
+        if stream.read(&mut byte).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).map_err(|_| invalid_data("request isn't UTF-8"))?;
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(invalid_data("malformed request line"));
+    };
+    if method.is_empty() {
+        return Err(invalid_data("malformed request line"));
+    }
+
+    let mut length = 0;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid_data("invalid Content-Length"))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(invalid_data("request body too large"));
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+    Ok(Request {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or_default().to_string(),
+        body,
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
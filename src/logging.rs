@@ -0,0 +1,470 @@
+use chrono::{Local, SecondsFormat};
+use env_filter::Filter;
+use env_logger::fmt::Formatter;
+use env_logger::{Builder, Target};
+use log::kv::{self, Key, Source, Value, VisitSource};
+use log::{info, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{OnceLock, RwLock};
+use std::{env, str};
+
+/// Bytes of each chunk logged by `preview` when no length is given.
+const DEFAULT_PREVIEW: usize = 64;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// How much of the data forwarded by a bridge is logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum PayloadLog {
+    /// Nothing, not even sizes.
+    Off,
+    /// The size of each chunk.
+    Size,
+    /// The size and up to this many bytes of each chunk.
+    Preview(usize),
+    /// The size and contents of each chunk.
+    Full,
+}
+
+impl PayloadLog {
+    /// Whether chunk contents are logged, which rules out splicing.
+    pub fn logs_data(self) -> bool {
+        matches!(self, PayloadLog::Preview(_) | PayloadLog::Full)
+    }
+}
+
+impl fmt::Display for PayloadLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadLog::Off => f.write_str("off"),
+            PayloadLog::Size => f.write_str("size"),
+            PayloadLog::Preview(n) => write!(f, "preview:{}", n),
+            PayloadLog::Full => f.write_str("full"),
+        }
+    }
+}
+
+impl FromStr for PayloadLog {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "off" => Ok(PayloadLog::Off),
+            "size" => Ok(PayloadLog::Size),
+            "preview" => Ok(PayloadLog::Preview(DEFAULT_PREVIEW)),
+            "full" => Ok(PayloadLog::Full),
+            _ => match s.strip_prefix("preview:").map(str::parse) {
+                Some(Ok(n)) if n > 0 => Ok(PayloadLog::Preview(n)),
+                _ => Err(format!(
+                    "`{}` is not a payload logging mode (off, size, preview[:BYTES] or full)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for PayloadLog {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Writes records through env_logger, behind a filter that can be replaced
+/// while running.
+struct Logger {
+    filter: RwLock<Filter>,
+    output: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.read().unwrap().matches(record) {
+            self.output.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.output.flush();
+    }
+}
+
+/// Installs the logger. Without a `level`, `RUST_LOG` is used if set, so
+/// it can also filter by module, and `info` otherwise.
+pub fn setup_logger(
+    level: Option<LevelFilter>,
+    format: LogFormat,
+    file: Option<RotatingFile>,
+) -> io::Result<()> {
+    let mut filter = env_filter::Builder::new();
+    match (level, env::var("RUST_LOG")) {
+        (Some(level), _) => filter.filter_level(level),
+        (None, Ok(spec)) => filter.parse(&spec),
+        (None, Err(_)) => filter.filter_level(LevelFilter::Info),
+    };
+    let filter = filter.build();
+    let max_level = filter.filter();
+
+    let mut builder = Builder::new();
+    match format {
+        LogFormat::Text => builder.format(format_text),
+        LogFormat::Json => builder.format(format_json),
+    };
+    if let Some(file) = file {
+        builder.target(Target::Pipe(Box::new(file)));
+    }
+    let logger = Logger {
+        filter: RwLock::new(filter),
+        output: builder.filter_level(LevelFilter::Trace).build(),
+    };
+    if LOGGER.set(logger).is_err() {
+        return Err(io::Error::other("logger already initialized"));
+    }
+    log::set_logger(LOGGER.get().unwrap()).map_err(io::Error::other)?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Replaces the log filter with a single `level`, dropping any per-module
+/// filters from `RUST_LOG`.
+pub fn set_level(level: LevelFilter) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    let previous = log::max_level();
+    *logger.filter.write().unwrap() = env_filter::Builder::new().filter_level(level).build();
+    log::set_max_level(level);
+    // Logged at the more severe of the two levels so the change shows either way.
+    log::log!(
+        previous.min(level).to_level().unwrap_or(log::Level::Error),
+        "Log level set to {}",
+        level.as_str().to_lowercase()
+    );
+}
+
+/// Moves the log level one step towards `trace` (`up`) or `off`.
+pub fn step_level(up: bool) {
+    let current = log::max_level();
+    let next = if up {
+        LevelFilter::iter().find(|&level| level > current)
+    } else {
+        LevelFilter::iter().filter(|&level| level < current).last()
+    };
+    match next {
+        Some(level) => set_level(level),
+        None => info!("Log level is already {}", current.as_str().to_lowercase()),
+    }
+}
+
+fn format_text(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    writeln!(
+        buf,
+        "{} [{}] - {}{}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        record.level(),
+        Prefix(record.key_values()),
+        record.args()
+    )
+}
+
+/// Writes `timestamp` and `level`, then every key-value on the record, then
+/// `message`, as one JSON object.
+fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut fields = vec![
+        (
+            "timestamp".to_string(),
+            Local::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, false)
+                .into(),
+        ),
+        ("level".to_string(), record.level().as_str().into()),
+    ];
+    let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+    fields.push(("message".to_string(), record.args().to_string().into()));
+
+    buf.write_all(b"{")?;
+    for (i, (key, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            buf.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut *buf, key)?;
+        buf.write_all(b":")?;
+        serde_json::to_writer(&mut *buf, value)?;
+    }
+    writeln!(buf, "}}")
+}
+
+struct JsonFields<'a>(&'a mut Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = match value.to_u64() {
+            Some(number) => number.into(),
+            None => value.to_string().into(),
+        };
+        self.0.push((key.as_str().to_string(), value));
+        Ok(())
+    }
+}
+
+/// Names the bridge and session a record belongs to, e.g.
+/// `default #3 container1->container2: `.
+struct Prefix<'a>(&'a dyn Source);
+
+impl fmt::Display for Prefix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let get = |key| self.0.get(Key::from(key));
+        let Some(bridge) = get("bridge") else {
+            return Ok(());
+        };
+        write!(f, "{}", bridge)?;
+        if let Some(session) = get("session") {
+            write!(f, " #{}", session)?;
+        }
+        if let Some(direction) = get("direction") {
+            write!(f, " {}", direction)?;
+        }
+        f.write_str(": ")
+    }
+}
+
+/// A log file that is moved aside to `PATH.1` once it reaches `max_size`,
+/// shifting older ones up to `PATH.<max_files>`.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = rotate(&self.path, self.max_files)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Moves `path` to `PATH.1`, shifting older files up and dropping any past
+/// `PATH.<max_files>`, then opens a fresh file at `path`. With `max_files`
+/// of 0 the file is just emptied.
+pub fn rotate(path: &Path, max_files: usize) -> io::Result<File> {
+    let rotated = |n: usize| {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(format!(".{}", n));
+        PathBuf::from(rotated)
+    };
+    if max_files > 0 {
+        for n in (1..max_files).rev() {
+            match fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(path, rotated(1))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use log::Level;
+    use std::process;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects what a logger writes.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("docker-tcp-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: PathBuf) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn payload_modes_parse() {
+        for (mode, expected) in [
+            ("off", PayloadLog::Off),
+            ("size", PayloadLog::Size),
+            ("preview", PayloadLog::Preview(DEFAULT_PREVIEW)),
+            ("preview:16", PayloadLog::Preview(16)),
+            ("full", PayloadLog::Full),
+        ] {
+            assert_eq!(mode.parse(), Ok(expected), "{}", mode);
+        }
+        assert_eq!(PayloadLog::Preview(16).to_string(), "preview:16");
+        for mode in [
+            "",
+            "Full",
+            "preview:",
+            "preview:0",
+            "preview:-1",
+            "preview:x",
+            "sizes",
+        ] {
+            let error = mode.parse::<PayloadLog>().unwrap_err();
+            assert!(error.contains("not a payload logging mode"), "{}", error);
+        }
+
+        let mode: PayloadLog = serde_json::from_str("\"preview:8\"").unwrap();
+        assert_eq!(mode, PayloadLog::Preview(8));
+        assert!(serde_json::from_str::<PayloadLog>("\"loud\"").is_err());
+        assert!(serde_json::from_str::<PayloadLog>("8").is_err());
+    }
+
+    #[test]
+    fn json_records_carry_their_key_values() {
+        let output = Output::default();
+        let logger = Builder::new()
+            .format(format_json)
+            .target(Target::Pipe(Box::new(output.clone())))
+            .filter_level(LevelFilter::Trace)
+            .build();
+        let key_values = [
+            ("bridge", Value::from("db \"main\"")),
+            ("session", Value::from(3u64)),
+            ("direction", Value::from("container1->container2")),
+            ("bytes", Value::from(42u64)),
+        ];
+        logger.log(
+            &Record::builder()
+                .level(Level::Warn)
+                .args(format_args!("42 bytes"))
+                .key_values(&key_values)
+                .build(),
+        );
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+        let timestamp = json["timestamp"].as_str().unwrap();
+        assert!(
+            DateTime::parse_from_rfc3339(timestamp).is_ok(),
+            "{}",
+            timestamp
+        );
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["bridge"], "db \"main\"");
+        assert_eq!(json["session"], 3);
+        assert_eq!(json["direction"], "container1->container2");
+        assert_eq!(json["bytes"], 42);
+        assert_eq!(json["message"], "42 bytes");
+        assert!(output.starts_with("{\"timestamp\":"), "{}", output);
+        assert!(
+            output.ends_with(",\"message\":\"42 bytes\"}\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn files_rotate_by_size() {
+        let dir = temp_dir("log-rotation");
+        let path = dir.join("bridge.log");
+        let rotated = |n: usize| dir.join(format!("bridge.log.{}", n));
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(path.clone()).as_deref(), Some("four\nfive\n"));
+        assert_eq!(read(rotated(1)).as_deref(), Some("three\n"));
+        assert_eq!(read(rotated(2)).as_deref(), Some("one\ntwo\n"));
+        assert_eq!(read(rotated(3)), None);
+
+        // Reopening carries on from the existing file's size.
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        file.write_all(b"six\n").unwrap();
+        assert_eq!(read(path).as_deref(), Some("six\n"));
+        assert_eq!(read(rotated(1)).as_deref(), Some("four\nfive\n"));
+        assert_eq!(read(rotated(2)).as_deref(), Some("three\n"));
+        assert_eq!(read(rotated(3)), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_emptied_without_rotated_ones() {
+        let dir = temp_dir("log-no-rotation");
+        let path = dir.join("bridge.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 0).unwrap();
+        for line in ["one\n", "two\n", "three\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(path.clone()).as_deref(), Some("three\n"));
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+
+        rotate(&path, 0).unwrap();
+        assert_eq!(read(path).as_deref(), Some(""));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
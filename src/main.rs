@@ -1,132 +1,231 @@
-use chrono::Local;
-use env_logger::Builder;
-use log::{error, info, LevelFilter};
-use std::io::{self, Read, Write};
-use std::net::{SocketAddr, TcpStream};
-use std::str;
+mod admin;
+mod bridge;
+mod buffer;
+mod capture;
+mod cli;
+mod config;
+mod discovery;
+mod docker;
+mod endpoint;
+mod events;
+mod http;
+mod logging;
+mod metrics;
+mod payload;
+mod poison;
+mod record;
+mod rendezvous;
+mod replay;
+mod retry;
+mod session;
+mod signals;
+mod socket;
+#[cfg(target_os = "linux")]
+mod splice;
+mod supervisor;
+
+use admin::Bridges;
+use bridge::{BridgeSettings, ContainerBridge};
+use capture::Capture;
+use cli::{Cli, Command};
+use config::Config;
+use discovery::Discovery;
+use endpoint::Endpoint;
+use events::{DockerEvents, EventListener};
+use log::error;
+use logging::RotatingFile;
+use retry::EXIT_RETRIES_EXHAUSTED;
+use signals::{Reload, Shutdown};
+use std::io::{self, IsTerminal};
+use std::process::ExitCode;
+use std::sync::{Arc, Weak};
 use std::thread;
-use std::time::Duration;
+use supervisor::Supervisor;
 
-struct ContainerBridge {
-    container1_addr: SocketAddr,
-    container2_addr: SocketAddr,
+/// An error for malformed input, from a file, the Docker API or a client.
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
 }
 
-impl ContainerBridge {
-    fn new(container1_addr: SocketAddr, container2_addr: SocketAddr) -> Self {
-        ContainerBridge {
-            container1_addr,
-            container2_addr,
-        }
-    }
-
-    fn start(&self) -> std::io::Result<()> {
-        info!(
-            "Attempting to connect {} and {}",
-            self.container1_addr, self.container2_addr
-        );
-
-        loop {
-            match (
-                TcpStream::connect(self.container1_addr),
-                TcpStream::connect(self.container2_addr),
-            ) {
-                (Ok(stream1), Ok(stream2)) => {
-                    info!("Connected to both containers!");
-                    self.handle_connection(stream1, stream2)?;
-                }
-                _ => {
-                    error!("Couldn't connect to both containers. Retrying in 5 seconds...");
-                    std::thread::sleep(Duration::from_secs(5));
-                }
+/// Asks until a valid address is entered, or None once stdin ends.
+fn prompt_for_address(service: &str) -> Option<Endpoint> {
+    loop {
+        println!("Enter the address for {} (e.g., 127.0.0.1:3000):", service);
+        let mut input = String::new();
+        match io::stdin().read_line(&mut input) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => {
+                error!("Couldn't read the address: {}", e);
+                return None;
             }
         }
+        match input.trim().parse() {
+            Ok(addr) => return Some(addr),
+            Err(_) => println!("Invalid address. Please try again."),
+        }
     }
+}
 
-    fn handle_connection(
-        &self,
-        mut stream1: TcpStream,
-        mut stream2: TcpStream,
-    ) -> std::io::Result<()> {
-        let mut stream1_clone = stream1.try_clone()?;
-        let mut stream2_clone = stream2.try_clone()?;
-
-        let handle1 = thread::spawn(move || {
-            forward_data(&mut stream1, &mut stream2_clone, "Container1 -> Container2")
-        });
-
-        let handle2 = thread::spawn(move || {
-            forward_data(&mut stream2, &mut stream1_clone, "Container2 -> Container1")
-        });
-
-        handle1.join().unwrap()?;
-        handle2.join().unwrap()?;
-
-        Ok(())
+/// The settings given on the command line, with the capture file opened.
+fn settings(cli: &Cli) -> io::Result<BridgeSettings> {
+    let mut settings = cli.settings();
+    if let Some(path) = &cli.capture {
+        let capture = Capture::open(
+            path.clone(),
+            cli.capture_max_size,
+            cli.capture_interval,
+            cli.capture_max_files,
+        )
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        settings.capture = Some(Arc::new(capture));
     }
+    Ok(settings)
 }
 
-fn forward_data(from: &mut TcpStream, to: &mut TcpStream, direction: &str) -> std::io::Result<()> {
-    let mut buffer = [0; 1024];
-    loop {
-        match from.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(n) => {
-                let data = &buffer[..n];
-                info!("{}: {} bytes", direction, n);
+/// Starts following Docker events when something depends on containers'
+/// lifecycles, unless disabled.
+fn docker_events(cli: &Cli, wanted: bool) -> io::Result<Option<Arc<DockerEvents>>> {
+    if cli.no_docker_events || !wanted {
+        return Ok(None);
+    }
+    DockerEvents::spawn(cli.settings().docker).map(Some)
+}
 
-                // Try to display the data as UTF-8 string
-                match str::from_utf8(data) {
-                    Ok(s) => info!("{}: Data: {}", direction, s.trim()),
-                    Err(_) => info!("{}: Data: {:?} (non UTF-8)", direction, data),
-                }
+fn main() -> ExitCode {
+    let cli = Cli::parse_args();
+    let log_file = cli
+        .log_file
+        .clone()
+        .map(|path| RotatingFile::open(path, cli.log_max_size, cli.log_max_files))
+        .transpose();
+    if let Err(e) =
+        log_file.and_then(|file| logging::setup_logger(cli.log_level, cli.log_format, file))
+    {
+        eprintln!("Failed to initialize logger: {}", e);
+        return ExitCode::FAILURE;
+    }
 
-                to.write_all(data)?;
-            }
+    if let Some(Command::Replay(args)) = &cli.command {
+        return match replay::run(args, cli.settings().docker) {
+            Ok(report) if report.diverged() => ExitCode::from(replay::EXIT_DIVERGED),
+            Ok(_) => ExitCode::SUCCESS,
             Err(e) => {
-                error!("{}: Error reading data: {}", direction, e);
-                return Err(e);
+                error!("{}", e);
+                ExitCode::FAILURE
             }
+        };
+    }
+
+    if let Some(addr) = cli.metrics_listen {
+        if let Err(e) = metrics::serve(addr) {
+            error!("Couldn't serve metrics on {}: {}", addr, e);
+            return ExitCode::FAILURE;
         }
     }
-    info!("Connection from {} closed.", direction);
-    Ok(())
-}
 
-fn prompt_for_address(service: &str) -> SocketAddr {
-    loop {
-        println!("Enter the address for {} (e.g., 127.0.0.1:3000):", service);
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
-        match input.trim().parse() {
-            Ok(addr) => return addr,
-            Err(_) => println!("Invalid address. Please try again."),
+    match run(&cli) {
+        Ok(()) => ExitCode::from(signals::exit_status()),
+        Err(e) => {
+            error!("{}", e);
+            if supervisor::is_retries_exhausted(&e) {
+                ExitCode::from(EXIT_RETRIES_EXHAUSTED)
+            } else {
+                ExitCode::FAILURE
+            }
         }
     }
 }
 
-fn setup_logger() -> Result<(), io::Error> {
-    Builder::new()
-        .format(|buf, record| {
-            writeln!(
-                buf,
-                "{} [{}] - {}",
-                Local::now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                record.args()
-            )
-        })
-        .filter(None, LevelFilter::Info)
-        .init();
-    Ok(())
-}
+fn run(cli: &Cli) -> io::Result<()> {
+    let admin_only = cli.admin_listen.is_some() && cli.endpoints.is_empty();
+    if cli.config.is_some() || cli.discover || admin_only {
+        return run_supervised(cli);
+    }
+
+    let (container1, container2) = match &cli.endpoints[..] {
+        [container1, container2] => (container1.clone(), container2.clone()),
+        _ if io::stdin().is_terminal() => {
+            let prompt = |service| {
+                prompt_for_address(service)
+                    .unwrap_or_else(|| Cli::missing_endpoints("none were entered"))
+            };
+            (prompt("Container 1"), prompt("Container 2"))
+        }
+        _ => Cli::missing_endpoints("stdin is not a terminal to prompt for them"),
+    };
 
-fn main() -> std::io::Result<()> {
-    setup_logger().expect("Failed to initialize logger");
-    let container1_addr = prompt_for_address("Container 1");
-    let container2_addr = prompt_for_address("Container 2");
-    let bridge = ContainerBridge::new(container1_addr, container2_addr);
+    let bridge = Arc::new(ContainerBridge::new(
+        "default",
+        container1,
+        container2,
+        settings(cli)?,
+    ));
+    let events = docker_events(cli, bridge.uses_docker())?;
+    if let Some(events) = &events {
+        let listener: Weak<dyn EventListener> = Arc::downgrade(&bridge) as _;
+        events.subscribe(listener);
+    }
+    if let Some(addr) = &cli.admin_listen {
+        admin::serve(addr, Bridges::Single(bridge.clone()))?;
+    }
+    signals::spawn(None, Shutdown::Bridge(bridge.clone()), cli.drain_timeout)?;
     bridge.start()
 }
+
+/// Runs the bridges from the config file and any discovered from container
+/// labels under a supervisor.
+fn run_supervised(cli: &Cli) -> io::Result<()> {
+    let defaults = settings(cli)?;
+    let bridges = match &cli.config {
+        Some(path) => Config::load(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?
+            .bridges
+            .iter()
+            .map(|bridge| bridge.build(&defaults))
+            .collect::<io::Result<_>>()?,
+        None => Vec::new(),
+    };
+
+    let wanted = cli.discover || bridges.iter().any(ContainerBridge::uses_docker);
+    let events = docker_events(cli, wanted)?;
+    let supervisor = Supervisor::new(events.clone());
+    for bridge in bridges {
+        supervisor.add(bridge)?;
+    }
+    if let Some(addr) = &cli.admin_listen {
+        admin::serve(
+            addr,
+            Bridges::Supervised {
+                supervisor: supervisor.clone(),
+                defaults: Box::new(defaults.clone()),
+            },
+        )?;
+        supervisor.keep_running();
+    }
+    signals::spawn(
+        cli.config.clone().map(|path| Reload {
+            path,
+            supervisor: supervisor.clone(),
+            default_payload: defaults.log_payload,
+        }),
+        Shutdown::Supervisor(supervisor.clone()),
+        cli.drain_timeout,
+    )?;
+
+    if cli.discover {
+        let discovery = Discovery::new(supervisor.clone(), defaults, cli.discovery_interval);
+        if let Some(events) = &events {
+            let listener: Weak<dyn EventListener> = Arc::downgrade(&discovery) as _;
+            events.subscribe(listener);
+        }
+        supervisor.keep_running();
+        thread::Builder::new()
+            .name("discovery".to_string())
+            .spawn(move || discovery.run())?;
+    }
+    supervisor.wait()
+}
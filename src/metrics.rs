@@ -0,0 +1,411 @@
+use crate::bridge;
+use crate::http::{self, Listener, Request, Response};
+use crate::session::{Direction, SessionOutcome};
+use log::info;
+use std::fmt::{self, Write};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+const DIRECTIONS: [Direction; 2] = [Direction::ToContainer2, Direction::ToContainer1];
+const CONTAINERS: [&str; 2] = ["container1", "container2"];
+
+/// Upper bounds in seconds of the session duration buckets.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 1800.0, 3600.0,
+];
+/// Upper bounds in seconds of the time-to-first-byte buckets.
+const FIRST_BYTE_BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+];
+
+/// Serves the metrics at `http://ADDR/metrics` on the shared runtime.
+pub fn serve(addr: SocketAddr) -> io::Result<()> {
+    let runtime = bridge::runtime();
+    let listener = runtime.block_on(TcpListener::bind(addr))?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    runtime.spawn(http::serve(Listener::Tcp(listener), handle));
+    Ok(())
+}
+
+fn handle(request: Request) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => Response::ok("text/plain; version=0.0.4; charset=utf-8", render()),
+        (_, "/metrics") => Response::error(405, "Method not allowed"),
+        _ => Response::error(404, "Not found"),
+    }
+}
+
+/// Every bridge's metrics, for rendering. Bridges that are gone drop out.
+fn registry() -> &'static Mutex<Vec<Weak<BridgeMetrics>>> {
+    static REGISTRY: OnceLock<Mutex<Vec<Weak<BridgeMetrics>>>> = OnceLock::new();
+    REGISTRY.get_or_init(Mutex::default)
+}
+
+/// Counters and histograms of one bridge, indexed by direction or container.
+pub struct BridgeMetrics {
+    bridge: String,
+    bytes: [AtomicU64; 2],
+    chunks: [AtomicU64; 2],
+    active_sessions: AtomicI64,
+    sessions: AtomicU64,
+    outcomes: [AtomicU64; SessionOutcome::LABELS.len()],
+    connect_attempts: [AtomicU64; 2],
+    connect_failures: [AtomicU64; 2],
+    session_duration: Histogram,
+    first_byte: [Histogram; 2],
+}
+
+impl BridgeMetrics {
+    pub fn register(bridge: &str) -> Arc<Self> {
+        let metrics = Arc::new(BridgeMetrics {
+            bridge: bridge.to_string(),
+            bytes: Default::default(),
+            chunks: Default::default(),
+            active_sessions: AtomicI64::new(0),
+            sessions: AtomicU64::new(0),
+            outcomes: Default::default(),
+            connect_attempts: Default::default(),
+            connect_failures: Default::default(),
+            session_duration: Histogram::new(&DURATION_BUCKETS),
+            first_byte: [(); 2].map(|()| Histogram::new(&FIRST_BYTE_BUCKETS)),
+        });
+        let mut registry = registry().lock().unwrap();
+        registry.retain(|metrics| metrics.strong_count() > 0);
+        registry.push(Arc::downgrade(&metrics));
+        metrics
+    }
+
+    pub fn forwarded(&self, direction: Direction, bytes: usize) {
+        let i = direction.sender();
+        self.bytes[i].fetch_add(bytes as u64, Ordering::Relaxed);
+        self.chunks[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records how long into a session the first data in `direction` came.
+    pub fn first_byte(&self, direction: Direction, elapsed: Duration) {
+        self.first_byte[direction.sender()].observe(elapsed.as_secs_f64());
+    }
+
+    pub fn session_started(&self) {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        self.active_sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_ended(&self, duration: Duration) {
+        self.active_sessions.fetch_sub(1, Ordering::Relaxed);
+        self.session_duration.observe(duration.as_secs_f64());
+    }
+
+    pub fn session_outcome(&self, outcome: &SessionOutcome) {
+        self.outcomes[outcome.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records an attempt to connect to container1 (`side` 0) or container2.
+    pub fn connected(&self, side: usize, success: bool) {
+        self.connect_attempts[side].fetch_add(1, Ordering::Relaxed);
+        if !success {
+            self.connect_failures[side].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A histogram with cumulative buckets, as Prometheus expects them.
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    /// Sum of the observations, as `f64` bits.
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            if value <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) -> fmt::Result {
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            let count = bucket.load(Ordering::Relaxed);
+            writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {count}")?;
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}")?;
+        writeln!(out, "{name}_sum{{{labels}}} {sum}")?;
+        writeln!(out, "{name}_count{{{labels}}} {count}")
+    }
+}
+
+/// Renders every bridge's metrics in the Prometheus text format.
+pub fn render() -> String {
+    let bridges: Vec<_> = registry()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    let mut out = String::new();
+    let _ = render_families(&mut out, &bridges);
+    out
+}
+
+fn render_families(out: &mut String, bridges: &[Arc<BridgeMetrics>]) -> fmt::Result {
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    header(out, "docker_tcp_bytes_total", "counter", "Bytes forwarded.")?;
+    for metrics in bridges {
+        for direction in DIRECTIONS {
+            let labels = direction_labels(&metrics.bridge, direction);
+            let bytes = load(&metrics.bytes[direction.sender()]);
+            writeln!(out, "docker_tcp_bytes_total{{{labels}}} {bytes}")?;
+        }
+    }
+    header(
+        out,
+        "docker_tcp_chunks_total",
+        "counter",
+        "Chunks of data forwarded.",
+    )?;
+    for metrics in bridges {
+        for direction in DIRECTIONS {
+            let labels = direction_labels(&metrics.bridge, direction);
+            let chunks = load(&metrics.chunks[direction.sender()]);
+            writeln!(out, "docker_tcp_chunks_total{{{labels}}} {chunks}")?;
+        }
+    }
+    header(
+        out,
+        "docker_tcp_active_sessions",
+        "gauge",
+        "Sessions currently open.",
+    )?;
+    for metrics in bridges {
+        let labels = bridge_label(&metrics.bridge);
+        let active = metrics.active_sessions.load(Ordering::Relaxed);
+        writeln!(out, "docker_tcp_active_sessions{{{labels}}} {active}")?;
+    }
+    header(
+        out,
+        "docker_tcp_sessions_total",
+        "counter",
+        "Sessions started.",
+    )?;
+    for metrics in bridges {
+        let labels = bridge_label(&metrics.bridge);
+        let sessions = load(&metrics.sessions);
+        writeln!(out, "docker_tcp_sessions_total{{{labels}}} {sessions}")?;
+    }
+    header(
+        out,
+        "docker_tcp_session_outcomes_total",
+        "counter",
+        "Sessions ended, by how they ended.",
+    )?;
+    for metrics in bridges {
+        for (i, outcome) in SessionOutcome::LABELS.iter().enumerate() {
+            let labels = format!("{},outcome=\"{}\"", bridge_label(&metrics.bridge), outcome);
+            let ended = load(&metrics.outcomes[i]);
+            writeln!(out, "docker_tcp_session_outcomes_total{{{labels}}} {ended}")?;
+        }
+    }
+    header(
+        out,
+        "docker_tcp_connect_attempts_total",
+        "counter",
+        "Connection attempts to each container.",
+    )?;
+    for metrics in bridges {
+        for (i, container) in CONTAINERS.iter().enumerate() {
+            let labels = container_labels(&metrics.bridge, container);
+            let attempts = load(&metrics.connect_attempts[i]);
+            writeln!(
+                out,
+                "docker_tcp_connect_attempts_total{{{labels}}} {attempts}"
+            )?;
+        }
+    }
+    header(
+        out,
+        "docker_tcp_connect_failures_total",
+        "counter",
+        "Failed connection attempts to each container.",
+    )?;
+    for metrics in bridges {
+        for (i, container) in CONTAINERS.iter().enumerate() {
+            let labels = container_labels(&metrics.bridge, container);
+            let failures = load(&metrics.connect_failures[i]);
+            writeln!(
+                out,
+                "docker_tcp_connect_failures_total{{{labels}}} {failures}"
+            )?;
+        }
+    }
+    header(
+        out,
+        "docker_tcp_session_duration_seconds",
+        "histogram",
+        "How long sessions lasted.",
+    )?;
+    for metrics in bridges {
+        let labels = bridge_label(&metrics.bridge);
+        metrics
+            .session_duration
+            .render(out, "docker_tcp_session_duration_seconds", &labels)?;
+    }
+    header(
+        out,
+        "docker_tcp_first_byte_seconds",
+        "histogram",
+        "Time from the start of a session to the first data in each direction.",
+    )?;
+    for metrics in bridges {
+        for direction in DIRECTIONS {
+            let labels = direction_labels(&metrics.bridge, direction);
+            let histogram = &metrics.first_byte[direction.sender()];
+            histogram.render(out, "docker_tcp_first_byte_seconds", &labels)?;
+        }
+    }
+    Ok(())
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} {kind}")
+}
+
+fn bridge_label(bridge: &str) -> String {
+    format!("bridge=\"{}\"", escape(bridge))
+}
+
+fn direction_labels(bridge: &str, direction: Direction) -> String {
+    format!(
+        "{},direction=\"{}\"",
+        bridge_label(bridge),
+        direction.as_str()
+    )
+}
+
+fn container_labels(bridge: &str, container: &str) -> String {
+    format!("{},container=\"{}\"", bridge_label(bridge), container)
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of the sample rendered as `series`.
+    fn sample(out: &str, series: &str) -> f64 {
+        let line = out
+            .lines()
+            .find(|line| {
+                line.strip_prefix(series)
+                    .is_some_and(|rest| rest.starts_with(' '))
+            })
+            .unwrap_or_else(|| panic!("no {} in\n{}", series, out));
+        line[series.len() + 1..].parse().unwrap()
+    }
+
+    #[test]
+    fn histograms_are_cumulative() {
+        let histogram = Histogram::new(&FIRST_BYTE_BUCKETS);
+        for value in [0.003, 0.02, 0.02, 0.7, 10.0] {
+            histogram.observe(value);
+        }
+        let mut out = String::new();
+        histogram.render(&mut out, "h", "bridge=\"b\"").unwrap();
+
+        let buckets: Vec<f64> = out
+            .lines()
+            .filter(|line| line.starts_with("h_bucket"))
+            .map(|line| line.rsplit_once(' ').unwrap().1.parse().unwrap())
+            .collect();
+        assert_eq!(buckets.len(), FIRST_BYTE_BUCKETS.len() + 1);
+        assert!(buckets.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(sample(&out, "h_bucket{bridge=\"b\",le=\"0.001\"}"), 0.0);
+        assert_eq!(sample(&out, "h_bucket{bridge=\"b\",le=\"0.005\"}"), 1.0);
+        assert_eq!(sample(&out, "h_bucket{bridge=\"b\",le=\"0.025\"}"), 3.0);
+        assert_eq!(sample(&out, "h_bucket{bridge=\"b\",le=\"1\"}"), 4.0);
+        assert_eq!(sample(&out, "h_bucket{bridge=\"b\",le=\"5\"}"), 4.0);
+        assert_eq!(sample(&out, "h_bucket{bridge=\"b\",le=\"+Inf\"}"), 5.0);
+        assert_eq!(sample(&out, "h_count{bridge=\"b\"}"), 5.0);
+        assert!((sample(&out, "h_sum{bridge=\"b\"}") - 10.743).abs() < 1e-9);
+    }
+
+    #[test]
+    fn directions_are_keyed_by_sender() {
+        let metrics = BridgeMetrics::register("keyed");
+        metrics.forwarded(Direction::ToContainer1, 5);
+        metrics.forwarded(Direction::ToContainer1, 7);
+        metrics.forwarded(Direction::ToContainer2, 3);
+        metrics.first_byte(Direction::ToContainer1, Duration::from_millis(2));
+        let mut out = String::new();
+        render_families(&mut out, &[metrics]).unwrap();
+
+        let series = |name: &str, direction: &str| {
+            format!("{}{{bridge=\"keyed\",direction=\"{}\"}}", name, direction)
+        };
+        let [to_container2, to_container1] = DIRECTIONS.map(Direction::as_str);
+        assert_eq!(
+            sample(&out, &series("docker_tcp_bytes_total", to_container1)),
+            12.0
+        );
+        assert_eq!(
+            sample(&out, &series("docker_tcp_bytes_total", to_container2)),
+            3.0
+        );
+        assert_eq!(
+            sample(&out, &series("docker_tcp_chunks_total", to_container1)),
+            2.0
+        );
+        assert_eq!(
+            sample(&out, &series("docker_tcp_chunks_total", to_container2)),
+            1.0
+        );
+        let count = "docker_tcp_first_byte_seconds_count";
+        assert_eq!(sample(&out, &series(count, to_container1)), 1.0);
+        assert_eq!(sample(&out, &series(count, to_container2)), 0.0);
+    }
+
+    #[test]
+    fn bridge_labels_are_escaped() {
+        let metrics = BridgeMetrics::register("say \"hi\"\\\n");
+        let mut out = String::new();
+        render_families(&mut out, &[metrics]).unwrap();
+        let series = "docker_tcp_sessions_total{bridge=\"say \\\"hi\\\"\\\\\\n\"}";
+        assert_eq!(sample(&out, series), 0.0);
+        assert!(out.lines().all(|line| !line.ends_with("say \"hi\"\\")));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::logging::PayloadLog;
+use crate::session::{session_log, Direction, Session};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::str;
+
+/// Bytes on each line of a hexdump.
+const HEXDUMP_WIDTH: usize = 16;
+
+/// How logged chunk contents are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// UTF-8 text, or a list of bytes when the chunk isn't valid UTF-8.
+    Text,
+    /// An `xxd`-style hexdump: offsets, hex columns and an ASCII gutter.
+    Hex,
+    /// Printable text inline, with anything else escaped.
+    Mixed,
+}
+
+/// Logs a forwarded chunk as much as `mode` asks for, rendered in `format`.
+pub fn log(
+    session: &Session,
+    direction: Option<Direction>,
+    data: &[u8],
+    mode: PayloadLog,
+    format: PayloadFormat,
+) {
+    let shown = match mode {
+        PayloadLog::Off => return,
+        PayloadLog::Size => &[],
+        PayloadLog::Preview(limit) => &data[..data.len().min(limit)],
+        PayloadLog::Full => data,
+    };
+    session.log_bytes(direction, data.len());
+    if !mode.logs_data() {
+        return;
+    }
+
+    match format {
+        PayloadFormat::Text => log_text(session, direction, data, shown),
+        PayloadFormat::Hex => session_log!(
+            Info,
+            session,
+            direction,
+            "Data:\n{}{}",
+            hexdump(shown),
+            more(data, shown.len(), "\n")
+        ),
+        PayloadFormat::Mixed => session_log!(
+            Info,
+            session,
+            direction,
+            "Data: {}{}",
+            escape(shown),
+            more(data, shown.len(), " ")
+        ),
+    }
+}
+
+fn log_text(session: &Session, direction: Option<Direction>, data: &[u8], shown: &[u8]) {
+    match text(data, shown) {
+        Some(s) => session_log!(
+            Info,
+            session,
+            direction,
+            "Data: {}{}",
+            s.trim(),
+            more(data, s.len(), " ")
+        ),
+        None => session_log!(
+            Info,
+            session,
+            direction,
+            "Data: {:?}{} (non UTF-8)",
+            shown,
+            more(data, shown.len(), " ")
+        ),
+    }
+}
+
+/// The shown part of `data` as a UTF-8 string, if it is one.
+fn text<'a>(data: &[u8], shown: &'a [u8]) -> Option<&'a str> {
+    match str::from_utf8(shown) {
+        Ok(s) => Some(s),
+        // A preview may end partway through a character.
+        Err(e) if e.error_len().is_none() && shown.len() < data.len() => {
+            str::from_utf8(&shown[..e.valid_up_to()]).ok()
+        }
+        Err(_) => None,
+    }
+}
+
+/// Notes how much of `data` was left out after showing `shown` bytes.
+fn more(data: &[u8], shown: usize, separator: &str) -> String {
+    match data.len() - shown {
+        0 => String::new(),
+        n => format!("{}... ({} more bytes)", separator, n),
+    }
+}
+
+/// Renders `data` like `xxd` does, e.g.
+/// `00000000: 4865 6c6c 6f0a                           Hello.`
+pub fn hexdump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, bytes) in data.chunks(HEXDUMP_WIDTH).enumerate() {
+        if line > 0 {
+            dump.push('\n');
+        }
+        let _ = write!(dump, "{:08x}:", line * HEXDUMP_WIDTH);
+        for column in 0..HEXDUMP_WIDTH {
+            if column % 2 == 0 {
+                dump.push(' ');
+            }
+            match bytes.get(column) {
+                Some(byte) => {
+                    let _ = write!(dump, "{:02x}", byte);
+                }
+                None => dump.push_str("  "),
+            }
+        }
+        dump.push_str("  ");
+        dump.extend(bytes.iter().map(|&byte| match byte {
+            0x20..=0x7e => byte as char,
+            _ => '.',
+        }));
+    }
+    dump
+}
+
+/// Keeps printable text as it is and escapes everything else, e.g.
+/// `GET / HTTP/1.1\r\n` or `\x00\x00\x00\x08\x04\xd2\x16/`.
+pub fn escape(data: &[u8]) -> String {
+    let mut escaped = String::new();
+    for chunk in data.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c if c.is_control() => {
+                    let mut bytes = [0; 4];
+                    for byte in c.encode_utf8(&mut bytes).bytes() {
+                        let _ = write!(escaped, "\\x{:02x}", byte);
+                    }
+                }
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(escaped, "\\x{:02x}", byte);
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hexdump_pads_the_last_line() {
+        assert_eq!(
+            hexdump(b"Hello\n"),
+            "00000000: 4865 6c6c 6f0a                           Hello."
+        );
+        assert_eq!(
+            hexdump(b"0123456789abcdef\x00\xff\x7f"),
+            "00000000: 3031 3233 3435 3637 3839 6162 6364 6566  0123456789abcdef\n\
+             00000010: 00ff 7f                                  ..."
+        );
+        assert_eq!(hexdump(b""), "");
+    }
+
+    #[test]
+    fn escapes_control_characters_and_invalid_utf8() {
+        assert_eq!(escape(b"GET / HTTP/1.1\r\n"), "GET / HTTP/1.1\\r\\n");
+        assert_eq!(escape(b"a\tb\\c"), "a\\tb\\\\c");
+        assert_eq!(escape(b"\x00\x08\x1b\x7f"), "\\x00\\x08\\x1b\\x7f");
+        // U+0085 is a control character encoded as two bytes.
+        assert_eq!(escape("\u{85}".as_bytes()), "\\xc2\\x85");
+        assert_eq!(escape("caf\u{e9}".as_bytes()), "caf\u{e9}");
+        assert_eq!(escape(b"ok\xff\xfe!"), "ok\\xff\\xfe!");
+        // A truncated character is escaped byte by byte.
+        assert_eq!(escape(&"\u{e9}".as_bytes()[..1]), "\\xc3");
+    }
+
+    #[test]
+    fn preview_may_cut_a_character() {
+        let data = "naïve".as_bytes();
+        // The preview ends after the first byte of `ï`.
+        assert_eq!(text(data, &data[..3]), Some("na"));
+        assert_eq!(text(data, data), Some("naïve"));
+        // Without more data behind it, a cut character is invalid.
+        assert_eq!(text(&data[..3], &data[..3]), None);
+        assert_eq!(text(b"\xff", b"\xff"), None);
+        assert_eq!(text(b"a\xffbc", b"a\xff"), None);
+    }
+}
@@ -0,0 +1,55 @@
+use log::error;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+/// Locks state shared between sessions even if one of them panicked while
+/// holding it. Only for state that a panic can't leave half-changed.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Locks a file that sessions write to. A session that panicked while
+/// writing may have left half a record behind, so writing to `path` stops.
+pub fn lock_file<'a, W>(file: &'a Mutex<Option<W>>, path: &Path) -> MutexGuard<'a, Option<W>> {
+    file.lock().unwrap_or_else(|poisoned| {
+        error!("A session panicked writing {}, stopping it", path.display());
+        file.clear_poison();
+        let mut file = poisoned.into_inner();
+        *file = None;
+        file
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn poison<T: Send>(mutex: &Mutex<T>) {
+        thread::scope(|scope| {
+            let panicked = scope.spawn(|| {
+                let _guard = mutex.lock().unwrap();
+                panic!("panicking with the lock held");
+            });
+            assert!(panicked.join().is_err());
+        });
+        assert!(mutex.is_poisoned());
+    }
+
+    #[test]
+    fn locks_survive_a_panic_while_held() {
+        let state = Mutex::new(vec![1, 2]);
+        poison(&state);
+        lock(&state).push(3);
+        assert!(!state.is_poisoned());
+        assert_eq!(*lock(&state), [1, 2, 3]);
+
+        let file = Mutex::new(Some(Vec::<u8>::new()));
+        poison(&file);
+        assert!(lock_file(&file, Path::new("test.out")).is_none());
+        assert!(!file.is_poisoned());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::invalid_data;
+use crate::poison;
+use crate::session::{Direction, Session};
+use chrono::{DateTime, Local, SecondsFormat};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Mutex;
+
+/// First line of a recording, describing the session.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub bridge: String,
+    pub session: u64,
+    pub started: String,
+    pub container1: String,
+    pub container2: String,
+}
+
+/// What happened in one direction of a session, `time` seconds after it
+/// started. Each data event is one chunk as it was read.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    Data {
+        time: f64,
+        direction: Direction,
+        /// Hex-encoded.
+        data: String,
+    },
+    /// The sender closed its side.
+    Close { time: f64, direction: Direction },
+    /// The connection was reset by the sender, or otherwise its receiver.
+    Reset {
+        time: f64,
+        direction: Direction,
+        by_sender: bool,
+    },
+}
+
+impl Event {
+    pub fn time(&self) -> f64 {
+        match *self {
+            Event::Data { time, .. } | Event::Close { time, .. } | Event::Reset { time, .. } => {
+                time
+            }
+        }
+    }
+
+    pub fn direction(&self) -> Direction {
+        match *self {
+            Event::Data { direction, .. }
+            | Event::Close { direction, .. }
+            | Event::Reset { direction, .. } => direction,
+        }
+    }
+}
+
+/// A session being written to `DIR/BRIDGE-STARTED-SESSION.jsonl`: a header
+/// line, then one JSON event per line. Session ids restart with the
+/// process, so the start time keeps recordings of earlier runs apart.
+pub struct Recording {
+    path: PathBuf,
+    /// None once writing has failed.
+    file: Mutex<Option<BufWriter<File>>>,
+}
+
+impl Recording {
+    pub fn create(dir: &Path, session: &Session) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let stem = file_stem(&session.bridge, &session.started, session.id);
+        let (path, file) = create_new(dir, &stem)?;
+        let mut file = BufWriter::new(file);
+        let header = Header {
+            bridge: session.bridge.clone(),
+            session: session.id,
+            started: session
+                .started
+                .to_rfc3339_opts(SecondsFormat::Millis, false),
+            container1: session.addrs[0].to_string(),
+            container2: session.addrs[1].to_string(),
+        };
+        serde_json::to_writer(&mut file, &header)?;
+        file.write_all(b"\n")?;
+        Ok(Recording {
+            path,
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    pub fn write(&self, event: &Event) {
+        let mut file = poison::lock_file(&self.file, &self.path);
+        let Some(writer) = file.as_mut() else {
+            return;
+        };
+        let result = serde_json::to_writer(&mut *writer, event)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"));
+        if let Err(e) = result {
+            error!(
+                "Couldn't write recording {}, stopping it: {}",
+                self.path.display(),
+                e
+            );
+            *file = None;
+        }
+    }
+
+    /// Flushes what's buffered once the session is over.
+    pub fn finish(&self) {
+        if let Some(writer) = poison::lock_file(&self.file, &self.path).as_mut() {
+            if let Err(e) = writer.flush() {
+                error!("Couldn't write recording {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// The bridge name can come from the admin API or a container label, so
+/// anything but a plain word is replaced to keep the file inside `DIR`.
+fn file_stem(bridge: &str, started: &DateTime<Local>, session: u64) -> String {
+    let bridge: String = bridge
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "{}-{}-{}",
+        bridge,
+        started.format("%Y%m%dT%H%M%S%.3f"),
+        session
+    )
+}
+
+/// Creates `DIR/STEM.jsonl`, or `DIR/STEM-N.jsonl` if that exists already,
+/// never overwriting an earlier recording.
+fn create_new(dir: &Path, stem: &str) -> io::Result<(PathBuf, File)> {
+    for n in 0.. {
+        let path = match n {
+            0 => dir.join(format!("{}.jsonl", stem)),
+            n => dir.join(format!("{}-{}.jsonl", stem, n)),
+        };
+        match File::options().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("ran out of recording file names")
+}
+
+/// Reads a recording written by `Recording`.
+pub fn read(path: &Path) -> io::Result<(Header, Vec<Event>)> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(invalid_data("empty recording")),
+    };
+    let events = lines
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect::<io::Result<_>>()?;
+    Ok((header, events))
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+pub fn from_hex(hex: &str) -> io::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid_data("odd number of hex digits"));
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            // `from_str_radix` would also take a sign.
+            str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| invalid_data("invalid hex digits"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::{env, process};
+
+    fn started() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 17, 9, 30, 5).unwrap()
+    }
+
+    #[test]
+    fn file_names_carry_the_start_time() {
+        assert_eq!(
+            file_stem("redis", &started(), 7),
+            "redis-20240517T093005.000-7"
+        );
+    }
+
+    #[test]
+    fn bridge_names_stay_inside_the_directory() {
+        for bridge in ["../../etc/passwd", "/tmp/x", "..", "a\\b"] {
+            let stem = file_stem(bridge, &started(), 1);
+            assert!(!stem.contains(['/', '\\']), "{}", stem);
+            assert!(!stem.contains(".."), "{}", stem);
+        }
+    }
+
+    #[test]
+    fn earlier_recordings_are_kept() {
+        let dir = env::temp_dir().join(format!("docker-tcp-record-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<_> = (0..3)
+            .map(|_| create_new(&dir, "bridge-1").unwrap().0)
+            .collect();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["bridge-1.jsonl", "bridge-1-1.jsonl", "bridge-1-2.jsonl"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let data = [0x00, 0x7f, 0x80, 0xff, b'a'];
+        assert_eq!(to_hex(&data), "007f80ff61");
+        assert_eq!(from_hex("007f80ff61").unwrap(), data);
+        assert_eq!(from_hex("ABcd").unwrap(), [0xab, 0xcd]);
+        assert!(from_hex("").unwrap().is_empty());
+        for (hex, error) in [
+            ("abc", "odd number"),
+            ("zz", "invalid hex"),
+            ("+1", "invalid hex"),
+            ("\u{e9}", "invalid hex"),
+        ] {
+            let result = from_hex(hex);
+            assert!(
+                result.as_ref().is_err_and(
+                    |e| e.kind() == io::ErrorKind::InvalidData && e.to_string().contains(error)
+                ),
+                "{}: {:?}",
+                hex,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let events = vec![
+            Event::Data {
+                time: 0.25,
+                direction: Direction::ToContainer2,
+                data: to_hex(b"ping"),
+            },
+            Event::Close {
+                time: 0.5,
+                direction: Direction::ToContainer2,
+            },
+            Event::Reset {
+                time: 1.0,
+                direction: Direction::ToContainer1,
+                by_sender: false,
+            },
+        ];
+        let header = r#"{"bridge":"b","session":1,"started":"","container1":"","container2":""}"#;
+        let mut lines = vec![header.to_string()];
+        for event in &events {
+            lines.push(serde_json::to_string(event).unwrap());
+        }
+        let path = env::temp_dir().join(format!("docker-tcp-events-{}.jsonl", process::id()));
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        assert_eq!(read(&path).unwrap().1, events);
+
+        for malformed in [
+            r#"{"event":"data","time":0,"direction":"container1->container2"}"#,
+            r#"{"event":"wave","time":0,"direction":"container1->container2"}"#,
+            r#"{"event":"close","time":0,"direction":"sideways"}"#,
+            "not json",
+        ] {
+            fs::write(&path, format!("{}\n{}\n", header, malformed)).unwrap();
+            let error = read(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", malformed);
+        }
+        fs::write(&path, "").unwrap();
+        assert!(read(&path).is_err_and(|e| e.to_string() == "empty recording"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+use crate::bridge::{describe_peer, is_closed};
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use tokio::net::TcpStream;
+
+/// Which waiting connection is paired first when a connection arrives on
+/// the other side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Pairing {
+    /// Pair the connection that has waited longest; reject newcomers when the queue is full.
+    Fifo,
+    /// Pair the most recent connection; evict the oldest when the queue is full.
+    Lifo,
+}
+
+/// Inbound connections waiting for a partner on the opposite listener.
+pub struct PendingClients {
+    name: String,
+    pairing: Pairing,
+    max_pending: usize,
+    queues: [VecDeque<TcpStream>; 2],
+}
+
+impl PendingClients {
+    pub fn new(name: &str, pairing: Pairing, max_pending: usize) -> Self {
+        PendingClients {
+            name: name.to_string(),
+            pairing,
+            max_pending,
+            queues: [VecDeque::new(), VecDeque::new()],
+        }
+    }
+
+    /// Offers a connection accepted on `side` (0 or 1). Returns the pair to
+    /// splice, ordered by side, if a partner was waiting.
+    pub fn offer(&mut self, side: usize, client: TcpStream) -> Option<(TcpStream, TcpStream)> {
+        let other = &mut self.queues[1 - side];
+        other.retain(|waiting| !is_closed(waiting));
+
+        let partner = match self.pairing {
+            Pairing::Fifo => other.pop_front(),
+            Pairing::Lifo => other.pop_back(),
+        };
+        if let Some(partner) = partner {
+            return Some(if side == 0 {
+                (client, partner)
+            } else {
+                (partner, client)
+            });
+        }
+
+        let queue = &mut self.queues[side];
+        queue.retain(|waiting| !is_closed(waiting));
+        if queue.len() >= self.max_pending {
+            match self.pairing {
+                Pairing::Fifo => {
+                    warn!(
+                        bridge = self.name.as_str();
+                        "{} connections already waiting on side {}, rejecting {}",
+                        queue.len(), side + 1, describe_peer(&client)
+                    );
+                    return None;
+                }
+                Pairing::Lifo => {
+                    if let Some(evicted) = queue.pop_front() {
+                        warn!(
+                            bridge = self.name.as_str();
+                            "Too many connections waiting on side {}, evicting {}",
+                            side + 1, describe_peer(&evicted)
+                        );
+                    }
+                }
+            }
+        }
+        queue.push_back(client);
+        info!(
+            bridge = self.name.as_str();
+            "{} connection(s) waiting on side {} for a partner",
+            queue.len(), side + 1
+        );
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge;
+    use std::future::Future;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use tokio::time;
+
+    /// Connects a client, returning it and the accepted end to offer.
+    async fn connect(listener: &TcpListener) -> (TcpStream, TcpStream) {
+        let addr = listener.local_addr().unwrap();
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (client.unwrap(), accepted.unwrap().0)
+    }
+
+    fn addr(client: &TcpStream) -> SocketAddr {
+        client.local_addr().unwrap()
+    }
+
+    /// Whether the accepted end of `client` was closed rather than kept.
+    async fn was_dropped(client: &mut TcpStream) -> bool {
+        let mut byte = [0; 1];
+        let read = time::timeout(Duration::from_millis(100), client.read(&mut byte));
+        matches!(read.await, Ok(Ok(0)))
+    }
+
+    /// The clients the paired connections came from.
+    fn clients(pair: Option<(TcpStream, TcpStream)>) -> Option<[SocketAddr; 2]> {
+        pair.map(|(stream1, stream2)| [stream1, stream2].map(|s| s.peer_addr().unwrap()))
+    }
+
+    fn run<F: Future>(test: impl FnOnce(TcpListener) -> F) -> F::Output {
+        bridge::runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            test(listener).await
+        })
+    }
+
+    #[test]
+    fn pairs_are_ordered_by_side() {
+        run(|listener| async move {
+            let mut pending = PendingClients::new("test", Pairing::Fifo, 4);
+            for first in [0, 1] {
+                let (a, accepted_a) = connect(&listener).await;
+                let (b, accepted_b) = connect(&listener).await;
+                assert!(pending.offer(first, accepted_a).is_none());
+                let paired = clients(pending.offer(1 - first, accepted_b));
+                match first {
+                    0 => assert_eq!(paired, Some([addr(&a), addr(&b)])),
+                    _ => assert_eq!(paired, Some([addr(&b), addr(&a)])),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn fifo_pairs_the_longest_waiting_and_rejects_newcomers() {
+        run(|listener| async move {
+            let mut pending = PendingClients::new("test", Pairing::Fifo, 2);
+            let mut waiting = Vec::new();
+            for _ in 0..3 {
+                let (client, accepted) = connect(&listener).await;
+                assert!(pending.offer(0, accepted).is_none());
+                waiting.push(client);
+            }
+            assert!(!was_dropped(&mut waiting[0]).await);
+            assert!(was_dropped(&mut waiting[2]).await);
+
+            for expected in [addr(&waiting[0]), addr(&waiting[1])] {
+                let (partner, accepted) = connect(&listener).await;
+                let paired = clients(pending.offer(1, accepted));
+                assert_eq!(paired, Some([expected, addr(&partner)]));
+            }
+            let (_partner, accepted) = connect(&listener).await;
+            assert!(pending.offer(1, accepted).is_none());
+        });
+    }
+
+    #[test]
+    fn lifo_pairs_the_newest_and_evicts_the_oldest() {
+        run(|listener| async move {
+            let mut pending = PendingClients::new("test", Pairing::Lifo, 2);
+            let mut waiting = Vec::new();
+            for _ in 0..3 {
+                let (client, accepted) = connect(&listener).await;
+                assert!(pending.offer(0, accepted).is_none());
+                waiting.push(client);
+            }
+            assert!(was_dropped(&mut waiting[0]).await);
+            assert!(!was_dropped(&mut waiting[1]).await);
+
+            for expected in [addr(&waiting[2]), addr(&waiting[1])] {
+                let (partner, accepted) = connect(&listener).await;
+                let paired = clients(pending.offer(1, accepted));
+                assert_eq!(paired, Some([expected, addr(&partner)]));
+            }
+        });
+    }
+
+    #[test]
+    fn closed_waiters_are_dropped() {
+        run(|listener| async move {
+            let mut pending = PendingClients::new("test", Pairing::Fifo, 1);
+            let (gone, accepted) = connect(&listener).await;
+            assert!(pending.offer(0, accepted).is_none());
+            drop(gone);
+            time::sleep(Duration::from_millis(50)).await;
+
+            // The closed one neither counts towards the limit nor is paired.
+            let (client, accepted) = connect(&listener).await;
+            assert!(pending.offer(0, accepted).is_none());
+            let (partner, accepted) = connect(&listener).await;
+            let paired = clients(pending.offer(1, accepted));
+            assert_eq!(paired, Some([addr(&client), addr(&partner)]));
+        });
+    }
+}
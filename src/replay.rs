@@ -0,0 +1,375 @@
+use crate::bridge;
+use crate::docker::DockerClient;
+use crate::endpoint::Endpoint;
+use crate::payload;
+use crate::record::{self, Event};
+use crate::session::Direction;
+use log::{info, warn};
+use socket2::SockRef;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+use tokio::time::{self, Instant};
+
+/// Exit status when the live side didn't answer as recorded.
+pub const EXIT_DIVERGED: u8 = 4;
+
+/// Bytes around a divergence shown in the report.
+const SHOWN_BYTES: usize = 32;
+
+/// Which side of a recorded session the replay plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Role {
+    /// Play container1: connect to ADDRESS and send what container1 sent.
+    Client,
+    /// Play container2: accept a connection on ADDRESS and send what
+    /// container2 sent.
+    Server,
+}
+
+/// Replays one side of a session recorded with `--record` against a live
+/// container, comparing what it sends back with the recording.
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// Recording to replay
+    #[arg(value_name = "RECORDING")]
+    pub recording: PathBuf,
+
+    /// Where to connect as the client, or listen as the server, as HOST:PORT
+    /// or docker://CONTAINER:PORT
+    #[arg(value_name = "ADDRESS")]
+    pub address: Endpoint,
+
+    /// Which recorded side to play
+    #[arg(long = "as", value_enum, default_value_t = Role::Client)]
+    pub role: Role,
+
+    /// Send as fast as possible instead of keeping the recorded timing
+    #[arg(long)]
+    pub fast: bool,
+
+    /// Seconds to wait for each recorded response before reporting it missing
+    #[arg(long, value_name = "SECS", default_value = "5", value_parser = crate::cli::parse_seconds)]
+    pub response_timeout: Duration,
+}
+
+/// Tallies of a replay.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub sent: usize,
+    pub expected: usize,
+    pub received: usize,
+    pub divergences: usize,
+}
+
+impl Report {
+    pub fn diverged(&self) -> bool {
+        self.divergences > 0
+    }
+}
+
+pub fn run(args: &ReplayArgs, docker: DockerClient) -> io::Result<Report> {
+    let (header, events) = record::read(&args.recording)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", args.recording.display(), e)))?;
+    let role = match args.role {
+        Role::Client => "client",
+        Role::Server => "server",
+    };
+    info!(
+        "Replaying session {} of bridge {} (container1 {}, container2 {}) as the {}",
+        header.session, header.bridge, header.container1, header.container2, role
+    );
+    bridge::runtime().block_on(async {
+        let endpoint = args.address.clone();
+        let addr = task::spawn_blocking(move || endpoint.resolve(&docker)).await??;
+        let stream = match args.role {
+            Role::Client => TcpStream::connect(addr).await?,
+            Role::Server => {
+                let listener = TcpListener::bind(addr).await?;
+                info!("Waiting for a connection on {}", addr);
+                listener.accept().await?.0
+            }
+        };
+        let mut replay = Replay {
+            args,
+            stream,
+            ours: match args.role {
+                Role::Client => Direction::ToContainer2,
+                Role::Server => Direction::ToContainer1,
+            },
+            received: Vec::new(),
+            offset: 0,
+            closed: false,
+            report: Report::default(),
+        };
+        replay.run(&events).await?;
+        let report = replay.report;
+        info!(
+            "Replay finished: sent {} bytes, received {} of {} expected bytes, {} divergences",
+            report.sent, report.received, report.expected, report.divergences
+        );
+        Ok(report)
+    })
+}
+
+struct Replay<'a> {
+    args: &'a ReplayArgs,
+    stream: TcpStream,
+    /// The direction the replay sends in.
+    ours: Direction,
+    /// Received bytes not yet compared with the recording.
+    received: Vec<u8>,
+    /// Position in the peer's stream of `received`.
+    offset: usize,
+    /// Whether the peer has closed its side.
+    closed: bool,
+    report: Report,
+}
+
+impl Replay<'_> {
+    async fn run(&mut self, events: &[Event]) -> io::Result<()> {
+        let started = Instant::now();
+        for event in events {
+            let ours = match *event {
+                Event::Reset {
+                    direction,
+                    by_sender,
+                    ..
+                } => (direction == self.ours) == by_sender,
+                _ => event.direction() == self.ours,
+            };
+            if !ours {
+                self.expect(event).await?;
+                continue;
+            }
+            if !self.args.fast {
+                time::sleep_until(started + Duration::from_secs_f64(event.time())).await;
+            }
+            match event {
+                Event::Data { data, .. } => {
+                    let data = record::from_hex(data)?;
+                    self.stream.write_all(&data).await?;
+                    self.report.sent += data.len();
+                }
+                Event::Close { .. } => self.stream.shutdown().await?,
+                Event::Reset { .. } => {
+                    SockRef::from(&self.stream).set_linger(Some(Duration::ZERO))?;
+                    return Ok(());
+                }
+            }
+        }
+        if !self.received.is_empty() {
+            self.diverged(&format!(
+                "{} unexpected bytes at the end",
+                self.received.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the peer does what it did in the recording: sends the same
+    /// data, closes its side or resets the connection.
+    async fn expect(&mut self, event: &Event) -> io::Result<()> {
+        match event {
+            Event::Data { data, .. } => {
+                let expected = record::from_hex(data)?;
+                self.report.expected += expected.len();
+                while self.received.len() < expected.len() && self.read().await {}
+
+                let compared = self.received.len().min(expected.len());
+                let mismatch = (0..compared).find(|&i| self.received[i] != expected[i]);
+                if let Some(at) = mismatch {
+                    let end = |data: &[u8]| data.len().min(at + SHOWN_BYTES);
+                    self.diverged(&format!(
+                        "differs at byte {}, expected:\n{}\ngot:\n{}",
+                        self.offset + at,
+                        payload::hexdump(&expected[at..end(&expected)]),
+                        payload::hexdump(&self.received[at..end(&self.received)])
+                    ));
+                } else if compared < expected.len() {
+                    self.diverged(&format!(
+                        "only {} of {} bytes arrived at byte {}",
+                        compared,
+                        expected.len(),
+                        self.offset
+                    ));
+                }
+                self.received.drain(..compared);
+                self.offset += compared;
+            }
+            Event::Close { .. } | Event::Reset { .. } => {
+                while !self.closed && self.read().await {}
+                if !self.received.is_empty() {
+                    self.diverged(&format!(
+                        "{} unexpected bytes before closing",
+                        self.received.len()
+                    ));
+                    self.offset += self.received.len();
+                    self.received.clear();
+                } else if !self.closed {
+                    self.diverged("didn't close its side");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads more from the peer. Returns false once it has closed or the
+    /// response timeout passes.
+    async fn read(&mut self) -> bool {
+        if self.closed {
+            return false;
+        }
+        let mut buffer = [0; 16 * 1024];
+        match time::timeout(self.args.response_timeout, self.stream.read(&mut buffer)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => {
+                self.closed = true;
+                false
+            }
+            Ok(Ok(n)) => {
+                self.received.extend_from_slice(&buffer[..n]);
+                self.report.received += n;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn diverged(&mut self, what: &str) {
+        self.report.divergences += 1;
+        let peer = match self.ours {
+            Direction::ToContainer2 => Direction::ToContainer1,
+            Direction::ToContainer1 => Direction::ToContainer2,
+        };
+        warn!("Diverged from the recording: {} {}", peer.as_str(), what);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::{BridgeSettings, ContainerBridge, Mode};
+    use std::io::{Read, Write};
+    use std::net::{self, Shutdown, SocketAddr};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::{env, fs, process, thread};
+
+    fn free_addr() -> SocketAddr {
+        net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn connect(addr: SocketAddr) -> net::TcpStream {
+        for _ in 0..500 {
+            if let Ok(stream) = net::TcpStream::connect(addr) {
+                return stream;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("couldn't connect to {}", addr);
+    }
+
+    /// Sends `request` and half-closes, then reads the reply to the end.
+    fn exchange(mut stream: net::TcpStream, request: &[u8]) -> Vec<u8> {
+        stream.write_all(request).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        reply
+    }
+
+    /// Reads the request to the end and answers with `reply`.
+    fn answer(mut stream: net::TcpStream, reply: &[u8]) -> Vec<u8> {
+        let mut request = Vec::new();
+        stream.read_to_end(&mut request).unwrap();
+        stream.write_all(reply).unwrap();
+        request
+    }
+
+    /// Records one ping/pong session through a bridge in listen mode.
+    fn record_session(dir: &Path) -> PathBuf {
+        let upstream = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let listen = free_addr();
+        let bridge = Arc::new(ContainerBridge::new(
+            "replayed",
+            Endpoint::Host(listen.to_string()),
+            Endpoint::Host(upstream.local_addr().unwrap().to_string()),
+            BridgeSettings {
+                mode: Mode::Listen,
+                once: true,
+                record: Some(dir.to_path_buf()),
+                ..Default::default()
+            },
+        ));
+        let running = thread::spawn(move || bridge.start());
+        let server = thread::spawn(move || answer(upstream.accept().unwrap().0, b"pong"));
+        assert_eq!(exchange(connect(listen), b"ping"), b"pong");
+        assert_eq!(server.join().unwrap(), b"ping");
+        running.join().unwrap().unwrap();
+
+        let mut recordings: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(recordings.len(), 1);
+        recordings.pop().unwrap()
+    }
+
+    fn replay(recording: &Path, address: SocketAddr, role: Role) -> Report {
+        let args = ReplayArgs {
+            recording: recording.to_path_buf(),
+            address: Endpoint::Host(address.to_string()),
+            role,
+            fast: true,
+            response_timeout: Duration::from_secs(1),
+        };
+        run(&args, DockerClient::default()).unwrap()
+    }
+
+    fn replay_as_client(recording: &Path, reply: &'static [u8]) -> Report {
+        let server = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        let server = thread::spawn(move || answer(server.accept().unwrap().0, reply));
+        let report = replay(recording, address, Role::Client);
+        assert_eq!(server.join().unwrap(), b"ping");
+        report
+    }
+
+    fn replay_as_server(recording: &Path, request: &'static [u8]) -> Report {
+        let address = free_addr();
+        let client = thread::spawn(move || exchange(connect(address), request));
+        let report = replay(recording, address, Role::Server);
+        assert_eq!(client.join().unwrap(), b"pong");
+        report
+    }
+
+    #[test]
+    fn replays_a_recorded_session() {
+        let dir = env::temp_dir().join(format!("docker-tcp-replay-{}", process::id()));
+        let recording = record_session(&dir);
+
+        let report = replay_as_client(&recording, b"pong");
+        assert!(!report.diverged(), "{:?}", report);
+        assert_eq!((report.sent, report.expected, report.received), (4, 4, 4));
+        let report = replay_as_client(&recording, b"pang");
+        assert_eq!(report.divergences, 1);
+        // Closing early leaves the response short.
+        let report = replay_as_client(&recording, b"po");
+        assert_eq!(report.divergences, 1);
+
+        let report = replay_as_server(&recording, b"ping");
+        assert!(!report.diverged(), "{:?}", report);
+        assert_eq!((report.sent, report.expected, report.received), (4, 4, 4));
+        let report = replay_as_server(&recording, b"pi");
+        assert_eq!(report.divergences, 1);
+        let report = replay_as_server(&recording, b"ping, and more");
+        assert_eq!(report.divergences, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Exit status used when a bridge gives up after `max_attempts`.
+pub const EXIT_RETRIES_EXHAUSTED: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backoff {
+    /// Wait `interval` between every attempt.
+    Fixed,
+    /// Start at `interval` and multiply it after every failed attempt, up to `max_interval`.
+    Exponential,
+}
+
+/// How a bridge waits between failed connection attempts.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub backoff: Backoff,
+    pub interval: Duration,
+    pub max_interval: Duration,
+    pub multiplier: f64,
+    /// Fraction of each delay that is randomised, from 0 (none) to 1.
+    pub jitter: f64,
+    pub max_attempts: Option<u32>,
+    /// Connect timeouts for container1 and container2.
+    pub connect_timeouts: [Option<Duration>; 2],
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            backoff: Backoff::Fixed,
+            interval: Duration::from_secs(5),
+            max_interval: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.0,
+            max_attempts: None,
+            connect_timeouts: [None, None],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!(
+                "jitter must be between 0 and 1, got {}",
+                self.jitter
+            ));
+        }
+        if self.multiplier.is_nan() || self.multiplier < 1.0 {
+            return Err(format!(
+                "backoff multiplier must be at least 1, got {}",
+                self.multiplier
+            ));
+        }
+        // Even exponential backoff never grows from zero, so this would
+        // spin on a container that is down.
+        if self.interval.is_zero() {
+            return Err("retry interval must be greater than zero".to_string());
+        }
+        if self.max_attempts == Some(0) {
+            return Err("max_attempts must be greater than zero".to_string());
+        }
+        if self.connect_timeouts.contains(&Some(Duration::ZERO)) {
+            return Err("connect timeouts must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    /// Delay before retrying after `attempt` (counted from 1) failed, or
+    /// `None` when no attempts are left.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+        let delay = match self.backoff {
+            Backoff::Fixed => self.interval,
+            Backoff::Exponential => {
+                let exponent = attempt.saturating_sub(1).min(1024) as i32;
+                let factor = self.multiplier.powi(exponent);
+                Duration::try_from_secs_f64(self.interval.as_secs_f64() * factor)
+                    .unwrap_or(self.max_interval)
+                    .min(self.max_interval)
+            }
+        };
+        Some(self.apply_jitter(delay))
+    }
+
+    fn apply_jitter(&self, delay: Duration) -> Duration {
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        // Spread evenly over delay * (1 - jitter ..= 1 + jitter).
+        let offset = self.jitter * (2.0 * random_fraction() - 1.0);
+        delay.mul_f64(1.0 + offset)
+    }
+
+    /// Describes the attempt count for logs, e.g. `3/10` or `3`.
+    pub fn attempts(&self, attempt: u32) -> String {
+        match self.max_attempts {
+            Some(max) => format!("{}/{}", attempt, max),
+            None => attempt.to_string(),
+        }
+    }
+}
+
+/// Error returned by a bridge that ran out of connection attempts.
+#[derive(Debug)]
+pub struct RetriesExhausted {
+    pub bridge: String,
+    pub attempts: u32,
+}
+
+impl fmt::Display for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: gave up after {} connection attempts",
+            self.bridge, self.attempts
+        )
+    }
+}
+
+impl std::error::Error for RetriesExhausted {}
+
+/// A uniformly distributed number in `0.0..1.0`; good enough for jitter
+/// without pulling in a random number generator.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+    hasher.write_u32(nanos);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exponential() -> RetryPolicy {
+        RetryPolicy {
+            backoff: Backoff::Exponential,
+            interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(60),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn fixed_backoff_waits_the_interval() {
+        let policy = RetryPolicy::default();
+        for attempt in [1, 2, 100] {
+            assert_eq!(policy.delay(attempt), Some(Duration::from_secs(5)));
+        }
+    }
+
+    #[test]
+    fn exponential_backoff_is_clamped_to_max_interval() {
+        let policy = exponential();
+        let delays: Vec<_> = (1..=8)
+            .map(|attempt| policy.delay(attempt).unwrap())
+            .collect();
+        let secs = |secs: &[u64]| {
+            secs.iter()
+                .map(|&s| Duration::from_secs(s))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(delays, secs(&[1, 2, 4, 8, 16, 32, 60, 60]));
+    }
+
+    #[test]
+    fn exponent_is_capped_for_huge_attempts() {
+        let policy = RetryPolicy {
+            multiplier: 1.0,
+            ..exponential()
+        };
+        assert_eq!(policy.delay(u32::MAX), Some(Duration::from_secs(1)));
+        // Overflowing to infinity falls back to max_interval.
+        let policy = exponential();
+        assert_eq!(policy.delay(u32::MAX), Some(Duration::from_secs(60)));
+        assert_eq!(policy.delay(1100), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = RetryPolicy {
+            interval: Duration::from_secs(10),
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..1000 {
+            let delay = policy.delay(1).unwrap();
+            assert!(
+                (Duration::from_secs(5)..=Duration::from_secs(15)).contains(&delay),
+                "{:?}",
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let policy = RetryPolicy {
+            max_attempts: Some(3),
+            ..RetryPolicy::default()
+        };
+        assert!(policy.delay(2).is_some());
+        assert_eq!(policy.delay(3), None);
+        assert_eq!(policy.delay(4), None);
+        assert_eq!(policy.attempts(2), "2/3");
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        assert!(RetryPolicy::default().validate().is_ok());
+        for (policy, error) in [
+            (
+                RetryPolicy {
+                    interval: Duration::ZERO,
+                    ..RetryPolicy::default()
+                },
+                "retry interval",
+            ),
+            (
+                RetryPolicy {
+                    interval: Duration::ZERO,
+                    ..exponential()
+                },
+                "retry interval",
+            ),
+            (
+                RetryPolicy {
+                    jitter: 1.5,
+                    ..RetryPolicy::default()
+                },
+                "jitter",
+            ),
+            (
+                RetryPolicy {
+                    multiplier: 0.5,
+                    ..exponential()
+                },
+                "multiplier",
+            ),
+            (
+                RetryPolicy {
+                    max_attempts: Some(0),
+                    ..RetryPolicy::default()
+                },
+                "max_attempts",
+            ),
+        ] {
+            let result = policy.validate();
+            assert!(
+                result.as_ref().is_err_and(|e| e.contains(error)),
+                "{:?}",
+                result
+            );
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use crate::capture::CaptureStream;
+use crate::record::{self, Event, Recording};
+use chrono::{DateTime, Local, SecondsFormat};
+use log::kv::{self, Key, Source, Value, VisitSource};
+use log::{Level, Record};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::TcpStream;
+
+/// Logs a message about a session, attaching the session (and optionally
+/// the direction) to the record as key-values.
+macro_rules! session_log {
+    ($level:ident, $session:expr, $direction:expr, $($arg:tt)+) => {
+        $session.log(log::Level::$level, $direction, format_args!($($arg)+))
+    };
+}
+pub(crate) use session_log;
+
+/// One pair of connections spliced together by a bridge.
+pub struct Session {
+    pub id: u64,
+    pub bridge: String,
+    pub started: DateTime<Local>,
+    /// Local and peer addresses of the connections to container1 and container2.
+    pub addrs: [ConnectionAddrs; 2],
+    /// Where the session's traffic is captured, if anywhere.
+    pub capture: Option<CaptureStream>,
+    pub recording: Option<Recording>,
+    /// Bytes forwarded from container1 and from container2.
+    pub bytes: Arc<[AtomicU64; 2]>,
+    clock: Instant,
+    /// When data was last forwarded, in nanoseconds on `clock`.
+    last_active: AtomicU64,
+}
+
+#[derive(Clone, Copy)]
+pub struct ConnectionAddrs {
+    pub local: Option<SocketAddr>,
+    pub peer: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "container1->container2")]
+    ToContainer2,
+    #[serde(rename = "container2->container1")]
+    ToContainer1,
+}
+
+/// What a session forwarded in one direction, as kept by captures and
+/// recordings.
+#[derive(Debug, Clone, Copy)]
+pub enum Traffic<'a> {
+    Data(&'a [u8]),
+    /// The sender closed its side.
+    Closed,
+    /// The connection was reset by the sender, or otherwise its receiver.
+    Reset {
+        by_sender: bool,
+    },
+}
+
+/// How a session ended.
+#[derive(Debug)]
+pub enum SessionOutcome {
+    /// Both containers closed their side.
+    Closed,
+    /// The bridge closed it: through the admin API, to reconnect or drain,
+    /// or because a container died.
+    ClosedByBridge,
+    /// One of the bridge's timeouts closed it.
+    TimedOut(String),
+    /// A container reset its connection or went away mid-transfer.
+    Reset(io::Error),
+    /// Forwarding failed for any other reason.
+    Failed(io::Error),
+    /// Forwarding panicked, with the panic's message.
+    Panicked(String),
+}
+
+impl Session {
+    pub fn new(id: u64, bridge: &str, streams: [&TcpStream; 2]) -> Self {
+        Session {
+            id,
+            bridge: bridge.to_string(),
+            started: Local::now(),
+            addrs: streams.map(|stream| ConnectionAddrs {
+                local: stream.local_addr().ok(),
+                peer: stream.peer_addr().ok(),
+            }),
+            capture: None,
+            recording: None,
+            bytes: Arc::default(),
+            clock: Instant::now(),
+            last_active: AtomicU64::new(0),
+        }
+    }
+
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.clock.elapsed()
+    }
+
+    pub fn count(&self, direction: Direction, bytes: usize) {
+        self.bytes[direction.sender()].fetch_add(bytes as u64, Ordering::Relaxed);
+        let now = self.elapsed().as_nanos() as u64;
+        self.last_active.fetch_max(now, Ordering::Relaxed);
+    }
+
+    /// How long it has been since data was forwarded either way.
+    pub fn idle_for(&self) -> std::time::Duration {
+        let last_active = self.last_active.load(Ordering::Relaxed);
+        self.elapsed()
+            .saturating_sub(std::time::Duration::from_nanos(last_active))
+    }
+
+    /// Whether anything keeps the session's traffic.
+    pub fn is_observed(&self) -> bool {
+        self.capture.is_some() || self.recording.is_some()
+    }
+
+    /// Passes traffic on to the session's capture and recording.
+    pub fn observe(&self, direction: Direction, traffic: Traffic) {
+        if let Some(capture) = &self.capture {
+            let capture = capture.direction(direction);
+            match traffic {
+                Traffic::Data(data) => capture.data(data),
+                Traffic::Closed => capture.fin(),
+                Traffic::Reset { by_sender } => capture.reset(by_sender),
+            }
+        }
+        if let Some(recording) = &self.recording {
+            let time = self.elapsed().as_secs_f64();
+            recording.write(&match traffic {
+                Traffic::Data(data) => Event::Data {
+                    time,
+                    direction,
+                    data: record::to_hex(data),
+                },
+                Traffic::Closed => Event::Close { time, direction },
+                Traffic::Reset { by_sender } => Event::Reset {
+                    time,
+                    direction,
+                    by_sender,
+                },
+            });
+        }
+    }
+
+    /// Closes off the capture and recording once the session is over.
+    pub fn finish(&self) {
+        if let Some(capture) = &self.capture {
+            capture.finish();
+        }
+        if let Some(recording) = &self.recording {
+            recording.finish();
+        }
+    }
+
+    pub fn log(&self, level: Level, direction: Option<Direction>, args: fmt::Arguments) {
+        self.emit(level, direction, None, args);
+    }
+
+    /// Logs the size of data forwarded in `direction`, with a `bytes` key.
+    pub fn log_bytes(&self, direction: Option<Direction>, bytes: usize) {
+        self.emit(
+            Level::Info,
+            direction,
+            Some(bytes),
+            format_args!("{} bytes", bytes),
+        );
+    }
+
+    fn emit(
+        &self,
+        level: Level,
+        direction: Option<Direction>,
+        bytes: Option<usize>,
+        args: fmt::Arguments,
+    ) {
+        if level > log::max_level() {
+            return;
+        }
+        let context = Context {
+            session: self,
+            started: self.started.to_rfc3339_opts(SecondsFormat::Millis, false),
+            direction,
+            bytes,
+        };
+        log::logger().log(
+            &Record::builder()
+                .level(level)
+                .target(module_path!())
+                .args(args)
+                .key_values(&context)
+                .build(),
+        );
+    }
+}
+
+impl Direction {
+    /// Index of the sending container: 0 for container1, 1 for container2.
+    pub fn sender(self) -> usize {
+        match self {
+            Direction::ToContainer2 => 0,
+            Direction::ToContainer1 => 1,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::ToContainer2 => "container1->container2",
+            Direction::ToContainer1 => "container2->container1",
+        }
+    }
+}
+
+impl SessionOutcome {
+    pub const LABELS: [&'static str; 6] = [
+        "closed",
+        "closed_by_bridge",
+        "timed_out",
+        "reset",
+        "failed",
+        "panicked",
+    ];
+
+    /// Position of the outcome's label in `LABELS`.
+    pub fn index(&self) -> usize {
+        match self {
+            SessionOutcome::Closed => 0,
+            SessionOutcome::ClosedByBridge => 1,
+            SessionOutcome::TimedOut(_) => 2,
+            SessionOutcome::Reset(_) => 3,
+            SessionOutcome::Failed(_) => 4,
+            SessionOutcome::Panicked(_) => 5,
+        }
+    }
+
+    /// Closing and timing out are how sessions are meant to end.
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            SessionOutcome::Reset(_) | SessionOutcome::Failed(_) | SessionOutcome::Panicked(_)
+        )
+    }
+
+    pub fn into_result(self) -> io::Result<()> {
+        match self {
+            SessionOutcome::Reset(e) | SessionOutcome::Failed(e) => Err(e),
+            SessionOutcome::Panicked(message) => {
+                Err(io::Error::other(format!("session panicked: {}", message)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for SessionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionOutcome::Closed => f.write_str("closed"),
+            SessionOutcome::ClosedByBridge => f.write_str("closed by the bridge"),
+            SessionOutcome::TimedOut(reason) => write!(f, "timed out, {}", reason),
+            SessionOutcome::Reset(e) => write!(f, "reset: {}", e),
+            SessionOutcome::Failed(e) => write!(f, "failed ({}): {}", e.kind(), e),
+            SessionOutcome::Panicked(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionAddrs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.local, self.peer) {
+            (Some(local), Some(peer)) => write!(f, "{} -> {}", local, peer),
+            (_, Some(peer)) => write!(f, "{}", peer),
+            _ => f.write_str("unknown"),
+        }
+    }
+}
+
+/// The key-values attached to a session's log records.
+struct Context<'a> {
+    session: &'a Session,
+    started: String,
+    direction: Option<Direction>,
+    bytes: Option<usize>,
+}
+
+impl Source for Context<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        let session = self.session;
+        visitor.visit_pair(Key::from("bridge"), Value::from(session.bridge.as_str()))?;
+        visitor.visit_pair(Key::from("session"), Value::from(session.id))?;
+        if let Some(direction) = self.direction {
+            visitor.visit_pair(Key::from("direction"), Value::from(direction.as_str()))?;
+        }
+        if let Some(bytes) = self.bytes {
+            visitor.visit_pair(Key::from("bytes"), Value::from(bytes))?;
+        }
+        visitor.visit_pair(Key::from("started"), Value::from(self.started.as_str()))?;
+        let keys = [
+            ("container1_local", "container1_peer"),
+            ("container2_local", "container2_peer"),
+        ];
+        for ((local, peer), addrs) in keys.into_iter().zip(&session.addrs) {
+            if let Some(addr) = &addrs.local {
+                visitor.visit_pair(Key::from(local), Value::from_display(addr))?;
+            }
+            if let Some(addr) = &addrs.peer {
+                visitor.visit_pair(Key::from(peer), Value::from_display(addr))?;
+            }
+        }
+        Ok(())
+    }
+}
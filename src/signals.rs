@@ -0,0 +1,137 @@
+use crate::bridge::{self, ContainerBridge};
+use crate::config::Config;
+use crate::logging::{self, PayloadLog};
+use crate::supervisor::Supervisor;
+use log::{error, info, warn};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
+use signal_hook::low_level::signal_name;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Exit status when sessions were still running at the drain deadline.
+pub const EXIT_DRAIN_TIMEOUT: u8 = 5;
+
+static EXIT_STATUS: AtomicU8 = AtomicU8::new(0);
+
+/// The config file supervised bridges were loaded from, re-read on SIGHUP.
+pub struct Reload {
+    pub path: PathBuf,
+    pub supervisor: Arc<Supervisor>,
+    /// Mode for bridges that don't set `log_payload` in the file.
+    pub default_payload: PayloadLog,
+}
+
+/// What SIGTERM and SIGINT drain and stop.
+#[derive(Clone)]
+pub enum Shutdown {
+    Bridge(Arc<ContainerBridge>),
+    Supervisor(Arc<Supervisor>),
+}
+
+/// Handles signals on a thread of their own: SIGUSR1 and SIGUSR2 raise and
+/// lower the log level, SIGHUP re-reads payload logging modes when there is
+/// a config file to `reload`, and SIGTERM and SIGINT shut down, giving
+/// sessions `drain_timeout` to finish. A second SIGTERM or SIGINT exits at
+/// once.
+pub fn spawn(
+    reload: Option<Reload>,
+    shutdown: Shutdown,
+    drain_timeout: Duration,
+) -> io::Result<()> {
+    let mut handled = vec![SIGUSR1, SIGUSR2, SIGTERM, SIGINT];
+    if reload.is_some() {
+        handled.push(SIGHUP);
+    }
+    let mut signals = Signals::new(handled)?;
+    thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            let mut shutting_down = false;
+            for signal in signals.forever() {
+                let name = signal_name(signal).unwrap_or("signal");
+                match signal {
+                    SIGTERM | SIGINT if shutting_down => {
+                        warn!("Received {} again, exiting now", name);
+                        process::exit(128 + signal);
+                    }
+                    SIGTERM | SIGINT => {
+                        shutting_down = true;
+                        info!(
+                            "Received {}, letting sessions finish for up to {:?}",
+                            name, drain_timeout
+                        );
+                        let shutdown = shutdown.clone();
+                        thread::Builder::new()
+                            .name("shutdown".to_string())
+                            .spawn(move || shutdown.run(drain_timeout))
+                            .expect("Failed to start the shutdown thread");
+                    }
+                    SIGUSR1 => logging::step_level(true),
+                    SIGUSR2 => logging::step_level(false),
+                    SIGHUP => {
+                        if let Some(reload) = &reload {
+                            reload.run();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// What the process should exit with once a shutdown has stopped the
+/// bridges.
+pub fn exit_status() -> u8 {
+    EXIT_STATUS.load(Ordering::Relaxed)
+}
+
+impl Shutdown {
+    fn run(&self, drain_timeout: Duration) {
+        let deadline = Instant::now() + drain_timeout;
+        let closed = match self {
+            Shutdown::Bridge(bridge) => bridge::runtime().block_on(bridge.drain(deadline)),
+            Shutdown::Supervisor(supervisor) => supervisor.drain(deadline),
+        };
+        if closed > 0 {
+            warn!(
+                "Closed {} session(s) still running after {:?}",
+                closed, drain_timeout
+            );
+            EXIT_STATUS.store(EXIT_DRAIN_TIMEOUT, Ordering::Relaxed);
+        } else {
+            info!("All sessions finished, shutting down");
+        }
+        match self {
+            Shutdown::Bridge(bridge) => bridge.stop(),
+            Shutdown::Supervisor(supervisor) => supervisor.stop(),
+        }
+    }
+}
+
+impl Reload {
+    /// Applies the file's payload logging modes to the running bridges.
+    /// Other changes to the file need a restart.
+    fn run(&self) {
+        info!("Reloading payload logging from {}", self.path.display());
+        let config = match Config::load(&self.path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Couldn't reload {}: {}", self.path.display(), e);
+                return;
+            }
+        };
+        for bridge in &config.bridges {
+            if let Some(running) = self.supervisor.get(&bridge.name) {
+                running.set_log_payload(bridge.log_payload.unwrap_or(self.default_payload));
+            }
+        }
+    }
+}
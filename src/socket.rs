@@ -0,0 +1,200 @@
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+/// Options for a bridge's connections to one container, applied whether
+/// they are dialed or accepted. Unset options keep the system defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Disable Nagle's algorithm (TCP_NODELAY).
+    pub nodelay: Option<bool>,
+    /// Idle time before keepalive probes are sent; setting any keepalive
+    /// option turns keepalive on.
+    pub keepalive: Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+    /// Unanswered probes before the connection is dropped.
+    pub keepalive_count: Option<u32>,
+    /// SO_SNDBUF and SO_RCVBUF.
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    /// SO_LINGER: how long closing waits for unsent data. Sessions close
+    /// lingering connections on a blocking thread.
+    pub linger: Option<Duration>,
+    /// TCP_USER_TIMEOUT: how long sent data may stay unacknowledged.
+    pub user_timeout: Option<Duration>,
+    /// Source address dialed connections are made from.
+    pub bind: Option<IpAddr>,
+    /// Network interface connections are bound to (SO_BINDTODEVICE).
+    pub interface: Option<String>,
+}
+
+impl SocketOptions {
+    /// Takes any option not set here from `defaults`.
+    pub fn or(self, defaults: &SocketOptions) -> SocketOptions {
+        SocketOptions {
+            nodelay: self.nodelay.or(defaults.nodelay),
+            keepalive: self.keepalive.or(defaults.keepalive),
+            keepalive_interval: self.keepalive_interval.or(defaults.keepalive_interval),
+            keepalive_count: self.keepalive_count.or(defaults.keepalive_count),
+            send_buffer_size: self.send_buffer_size.or(defaults.send_buffer_size),
+            recv_buffer_size: self.recv_buffer_size.or(defaults.recv_buffer_size),
+            linger: self.linger.or(defaults.linger),
+            user_timeout: self.user_timeout.or(defaults.user_timeout),
+            bind: self.bind.or(defaults.bind),
+            interface: self.interface.or_else(|| defaults.interface.clone()),
+        }
+    }
+
+    /// Dials `addr`, with the options set before connecting so buffer sizes
+    /// count towards the window negotiated in the handshake.
+    pub async fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        self.apply(SockRef::from(&socket))?;
+        self.bind_interface(SockRef::from(&socket))?;
+        if let Some(ip) = self.bind {
+            socket.bind(SocketAddr::new(ip, 0))?;
+        }
+        socket.connect(addr).await
+    }
+
+    /// Listens on `addr`. Accepted connections inherit the buffer sizes,
+    /// the rest is applied by `accepted`.
+    pub fn listen(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        socket.set_reuseaddr(true)?;
+        let sock = SockRef::from(&socket);
+        if let Some(size) = self.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+        }
+        self.bind_interface(sock)?;
+        socket.bind(addr)?;
+        socket.listen(1024)
+    }
+
+    pub fn accepted(&self, stream: &TcpStream) -> io::Result<()> {
+        self.apply(SockRef::from(stream))
+    }
+
+    fn apply(&self, socket: SockRef) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if self.keepalive.is_some()
+            || self.keepalive_interval.is_some()
+            || self.keepalive_count.is_some()
+        {
+            let mut keepalive = TcpKeepalive::new();
+            if let Some(time) = self.keepalive {
+                keepalive = keepalive.with_time(time);
+            }
+            if let Some(interval) = self.keepalive_interval {
+                keepalive = keepalive.with_interval(interval);
+            }
+            if let Some(count) = self.keepalive_count {
+                #[cfg(target_os = "linux")]
+                {
+                    keepalive = keepalive.with_retries(count);
+                }
+                #[cfg(not(target_os = "linux"))]
+                return Err(unsupported("keepalive_count"));
+            }
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(linger) = self.linger {
+            socket.set_linger(Some(linger))?;
+        }
+        if let Some(timeout) = self.user_timeout {
+            #[cfg(target_os = "linux")]
+            socket.set_tcp_user_timeout(Some(timeout))?;
+            #[cfg(not(target_os = "linux"))]
+            return Err(unsupported("user_timeout"));
+        }
+        Ok(())
+    }
+
+    fn bind_interface(&self, socket: SockRef) -> io::Result<()> {
+        if let Some(interface) = &self.interface {
+            #[cfg(target_os = "linux")]
+            socket.bind_device(Some(interface.as_bytes()))?;
+            #[cfg(not(target_os = "linux"))]
+            return Err(unsupported("interface"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported(option: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is only supported on Linux", option),
+    )
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::bridge;
+    use std::net::Ipv4Addr;
+
+    fn assert_applied(options: &SocketOptions, stream: &TcpStream) {
+        let socket = SockRef::from(stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), options.keepalive.unwrap());
+        assert_eq!(
+            socket.keepalive_interval().unwrap(),
+            options.keepalive_interval.unwrap()
+        );
+        assert_eq!(
+            socket.keepalive_retries().unwrap(),
+            options.keepalive_count.unwrap()
+        );
+        // Linux doubles the requested sizes to allow for bookkeeping.
+        assert!(socket.send_buffer_size().unwrap() >= options.send_buffer_size.unwrap());
+        assert!(socket.recv_buffer_size().unwrap() >= options.recv_buffer_size.unwrap());
+        assert_eq!(socket.linger().unwrap(), options.linger);
+        assert_eq!(socket.tcp_user_timeout().unwrap(), options.user_timeout);
+    }
+
+    #[test]
+    fn options_reach_dialed_and_accepted_sockets() {
+        let options = SocketOptions {
+            nodelay: Some(true),
+            keepalive: Some(Duration::from_secs(30)),
+            keepalive_interval: Some(Duration::from_secs(5)),
+            keepalive_count: Some(3),
+            send_buffer_size: Some(32 * 1024),
+            recv_buffer_size: Some(48 * 1024),
+            linger: Some(Duration::from_secs(1)),
+            user_timeout: Some(Duration::from_secs(10)),
+            ..SocketOptions::default()
+        };
+        bridge::runtime().block_on(async {
+            let listener = options.listen((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (dialed, accepted) = tokio::join!(options.connect(addr), listener.accept());
+            let (dialed, (accepted, _)) = (dialed.unwrap(), accepted.unwrap());
+            options.accepted(&accepted).unwrap();
+            assert_applied(&options, &dialed);
+            assert_applied(&options, &accepted);
+        });
+    }
+}
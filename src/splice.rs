@@ -0,0 +1,89 @@
+use std::io::{self, ErrorKind};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+use tokio::io::Interest;
+use tokio::net::TcpStream;
+
+/// Most a single splice moves; the default capacity of a pipe.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// A pipe that forwarded data is spliced through, so it moves from one
+/// socket to the other without being copied into userspace.
+pub struct Pipe {
+    read: OwnedFd,
+    write: OwnedFd,
+    /// Bytes spliced into the pipe but not yet out of it.
+    pending: usize,
+}
+
+impl Pipe {
+    pub fn new() -> io::Result<Pipe> {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for the two descriptors pipe2 writes.
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: pipe2 just opened these and nothing else owns them.
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        Ok(Pipe {
+            read,
+            write,
+            pending: 0,
+        })
+    }
+
+    /// Waits for data on `from` and splices what is available into the
+    /// pipe, which must have been drained. Returns 0 at end of stream.
+    pub async fn fill(&mut self, from: &TcpStream) -> io::Result<usize> {
+        let pipe = self.write.as_raw_fd();
+        loop {
+            from.readable().await?;
+            match from.try_io(Interest::READABLE, || {
+                splice(from.as_raw_fd(), pipe, PIPE_CAPACITY)
+            }) {
+                Ok(n) => {
+                    self.pending = n;
+                    return Ok(n);
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Splices everything in the pipe out to `to`.
+    pub async fn drain(&mut self, to: &TcpStream) -> io::Result<()> {
+        let pipe = self.read.as_raw_fd();
+        while self.pending > 0 {
+            to.writable().await?;
+            match to.try_io(Interest::WRITABLE, || {
+                splice(pipe, to.as_raw_fd(), self.pending)
+            }) {
+                Ok(n) => self.pending -= n,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    // SAFETY: both descriptors stay open for the duration of the call, and
+    // null offsets are valid for pipes and sockets.
+    let n = unsafe {
+        libc::splice(
+            from,
+            ptr::null_mut(),
+            to,
+            ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    };
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
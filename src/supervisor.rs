@@ -0,0 +1,211 @@
+use crate::bridge::{self, ContainerBridge};
+use crate::events::{DockerEvents, EventListener};
+use crate::retry::RetriesExhausted;
+use log::{error, info};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// Runs every bridge on its own thread, restarting any that stop with an
+/// error. Bridges can be added and removed while others keep running.
+pub struct Supervisor {
+    state: Mutex<State>,
+    changed: Condvar,
+    events: Option<Arc<DockerEvents>>,
+}
+
+#[derive(Default)]
+struct State {
+    running: HashMap<String, Running>,
+    /// Set when a bridge gives up for good, which ends the process.
+    failure: Option<io::Error>,
+    /// Keep waiting even when no bridges are left, e.g. while discovering.
+    persistent: bool,
+    /// Set once the process is shutting down, so no bridges are added.
+    shutting_down: bool,
+}
+
+struct Running {
+    bridge: Arc<ContainerBridge>,
+    thread: JoinHandle<()>,
+}
+
+impl Supervisor {
+    /// Bridges that dial Docker containers are subscribed to `events`.
+    pub fn new(events: Option<Arc<DockerEvents>>) -> Arc<Self> {
+        Arc::new(Supervisor {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+            events,
+        })
+    }
+
+    pub fn add(self: &Arc<Self>, bridge: ContainerBridge) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.shutting_down {
+            return Err(io::Error::other("shutting down"));
+        }
+        if state.running.contains_key(bridge.name()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("a bridge named `{}` is already running", bridge.name()),
+            ));
+        }
+
+        let bridge = Arc::new(bridge);
+        if let Some(events) = &self.events {
+            if bridge.uses_docker() {
+                let listener: Weak<dyn EventListener> = Arc::downgrade(&bridge) as _;
+                events.subscribe(listener);
+            }
+        }
+        let supervisor = Arc::downgrade(self);
+        let thread = thread::Builder::new()
+            .name(format!("bridge-{}", bridge.name()))
+            .spawn({
+                let bridge = bridge.clone();
+                move || {
+                    let failure = supervise(&bridge);
+                    if let Some(supervisor) = supervisor.upgrade() {
+                        supervisor.finish(&bridge, failure);
+                    }
+                }
+            })?;
+        state
+            .running
+            .insert(bridge.name().to_string(), Running { bridge, thread });
+        Ok(())
+    }
+
+    /// Stops the named bridge and waits for its thread to exit. Returns
+    /// whether such a bridge was running.
+    pub fn remove(&self, name: &str) -> bool {
+        let Some(running) = self.state.lock().unwrap().running.remove(name) else {
+            return false;
+        };
+        info!(bridge = name; "Stopping bridge");
+        running.bridge.stop();
+        if running.thread.join().is_err() {
+            error!(bridge = name; "Bridge thread panicked");
+        }
+        self.changed.notify_all();
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<ContainerBridge>> {
+        let state = self.state.lock().unwrap();
+        state
+            .running
+            .get(name)
+            .map(|running| running.bridge.clone())
+    }
+
+    /// The running bridges, by name.
+    pub fn bridges(&self) -> Vec<Arc<ContainerBridge>> {
+        let state = self.state.lock().unwrap();
+        let mut bridges: Vec<_> = state
+            .running
+            .values()
+            .map(|running| running.bridge.clone())
+            .collect();
+        bridges.sort_by(|a, b| a.name().cmp(b.name()));
+        bridges
+    }
+
+    /// Drains every bridge at once, see `ContainerBridge::drain`, and stops
+    /// new bridges from being added. Returns how many sessions had to be
+    /// closed.
+    pub fn drain(&self, deadline: Instant) -> usize {
+        self.state.lock().unwrap().shutting_down = true;
+        let bridges = self.bridges();
+        bridge::runtime().block_on(async {
+            let mut draining = JoinSet::new();
+            for bridge in bridges {
+                draining.spawn(async move { bridge.drain(deadline).await });
+            }
+            let mut closed = 0;
+            while let Some(result) = draining.join_next().await {
+                closed += result.unwrap_or(0);
+            }
+            closed
+        })
+    }
+
+    /// Stops every bridge, which makes `wait` return.
+    pub fn stop(&self) {
+        for bridge in self.bridges() {
+            self.remove(bridge.name());
+        }
+        self.changed.notify_all();
+    }
+
+    /// Makes `wait` keep blocking when no bridges are running.
+    pub fn keep_running(&self) {
+        self.state.lock().unwrap().persistent = true;
+    }
+
+    /// Blocks until no bridges are left running, or returns the error of
+    /// the first bridge that gives up.
+    pub fn wait(&self) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        let mut state = self
+            .changed
+            .wait_while(state, |state| {
+                let persistent = state.persistent && !state.shutting_down;
+                state.failure.is_none() && (persistent || !state.running.is_empty())
+            })
+            .unwrap();
+        state.failure.take().map_or(Ok(()), Err)
+    }
+
+    fn finish(&self, bridge: &Arc<ContainerBridge>, failure: Option<io::Error>) {
+        let mut state = self.state.lock().unwrap();
+        let current = state
+            .running
+            .get(bridge.name())
+            .is_some_and(|running| Arc::ptr_eq(&running.bridge, bridge));
+        if current {
+            state.running.remove(bridge.name());
+        }
+        if state.failure.is_none() {
+            state.failure = failure;
+        }
+        self.changed.notify_all();
+    }
+}
+
+/// Restarts the bridge until it finishes. Returns the error that made it
+/// give up, if any.
+fn supervise(bridge: &Arc<ContainerBridge>) -> Option<io::Error> {
+    loop {
+        match bridge.start() {
+            Ok(()) => {
+                info!(bridge = bridge.name(); "Bridge finished.");
+                return None;
+            }
+            Err(_) if bridge.is_stopped() => return None,
+            Err(e) if is_retries_exhausted(&e) => return Some(e),
+            Err(e) => {
+                let retry_interval = bridge.settings().retry.interval;
+                error!(
+                    bridge = bridge.name();
+                    "Bridge stopped: {}. Restarting in {:?}...",
+                    e, retry_interval
+                );
+                bridge.sleep(retry_interval);
+                if bridge.is_stopped() {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+pub fn is_retries_exhausted(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<RetriesExhausted>())
+}